@@ -1,33 +1,37 @@
 use std::io::{BufRead, BufReader};
 
 use knowledge::repository::*;
+use knowledge::writer::{KnowledgeWriter, WriterConf};
 
 
 fn create_repository() {
     let begin = std::time::Instant::now();
-    let (index, index_reader) = create_index("repository").unwrap();
+    let (index, index_reader) =
+        create_index("repository").expect("failed to create the repository, remove the existing one first");
+    let writer = KnowledgeWriter::open(&index, index_reader.clone(), WriterConf::default()).unwrap();
 
     let file = std::fs::File::open("data.json").unwrap();
-    let mut reader = BufReader::new(file);
-
-    let mut line = String::new();
-
-    let mut docs: Vec<KnownledgeDocument> = Vec::new();
-    loop {
-        if let Ok(n) = reader.read_line(&mut line) {
-            // println!("{},{}", n, line);
-            if n == 0 {
-                break;
-            }
-
-            if let Ok(doc) = serde_json::from_str::<KnownledgeDocument>(&*line) {
-                // add_doc(&index, data, &index_reader).unwrap(); //It cost expensively to commit the changes!!
-                docs.push(doc);
-            }
-            line.clear();
-        }
-    }
-    add_doc_in_batch(&index, &index_reader, docs).unwrap();
+    let reader = BufReader::new(file);
+
+    //feed the lines one by one, no need to keep all documents in memory
+    let docs = reader
+        .lines()
+        .map_while(Result::ok)
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            (
+                i + 1,
+                serde_json::from_str::<KnownledgeDocument>(&line).map_err(|e| e.to_string()),
+            )
+        });
+    let report = add_doc_stream(&index, &writer, docs, 1000).unwrap();
+    println!(
+        "accepted: {}, rejected: {}, commits: {}",
+        report.accepted,
+        report.rejected.len(),
+        report.commits
+    );
     let search = index_reader.searcher();
     assert_eq!(9774, search.num_docs());
     let end = std::time::Instant::now();
@@ -36,4 +40,4 @@ fn create_repository() {
 
 pub fn main(){
     create_repository();
-}
\ No newline at end of file
+}
@@ -1,4 +1,6 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use crate::importer::{TableFormat, TextEncoding};
 
 #[derive(Parser)]
 #[command(about = "Knowledge Application")]
@@ -10,5 +12,67 @@ pub struct KnowledgeArgument{
     pub port: u16,
 
     #[arg(short, long,default_value = "false")]
-    pub load: bool
+    pub load: bool,
+
+    /// Directory of the repository served as the default collection
+    #[arg(long, default_value = "repository")]
+    pub repository: String,
+
+    /// Directory of the named collections, one sub-directory each
+    #[arg(long, default_value = "collections")]
+    pub collections: String,
+
+    /// Configuration file, e.g. `configuration/config.toml`
+    #[arg(short, long)]
+    pub config: Option<String>,
+
+    /// Run a command on the repository and exit instead of starting the server
+    #[command(subcommand)]
+    pub command: Option<KnowledgeCommand>,
+}
+
+#[derive(Subcommand)]
+pub enum KnowledgeCommand {
+    /// Import a CSV or TSV file into a collection
+    Import {
+        /// Path of the file to import
+        file: String,
+
+        /// The collection to import into, its directory is found like the server does
+        #[arg(long, default_value = "default")]
+        collection: String,
+
+        #[arg(long, value_enum, default_value = "csv")]
+        format: TableFormat,
+
+        #[arg(long, value_enum, default_value = "auto")]
+        encoding: TextEncoding,
+
+        /// Whether the first row is the header, detected if absent
+        #[arg(long)]
+        has_header: Option<bool>,
+
+        /// Column mapping, e.g. `title:标题,body:内容`
+        #[arg(long)]
+        columns: Option<String>,
+    },
+    /// Export the live documents of a collection as NDJSON, the inverse of the bulk ingestion
+    Export {
+        /// Path of the NDJSON file to write
+        output: String,
+
+        /// The collection to export, its directory is found like the server does
+        #[arg(long, default_value = "default")]
+        collection: String,
+    },
+    /// Restore a collection from a snapshot, its current index is moved into the trash
+    ///
+    /// It fails if a server is writing the collection.
+    Restore {
+        /// Directory of the snapshot, e.g. `collections/.snapshots/default.20240101120000000`
+        snapshot: String,
+
+        #[arg(long, default_value = "default")]
+        collection: String,
+    },
 }
\ No newline at end of file
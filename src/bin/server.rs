@@ -1,9 +1,17 @@
-use axum::http::StatusCode;
+use axum::extract::DefaultBodyLimit;
 use axum::routing::{get, post, put};
 use axum::Router;
 use clap::Parser;
-use knowledge::agrument::KnowledgeArgument;
-use knowledge::router;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use knowledge::agrument::{KnowledgeArgument, KnowledgeCommand};
+use knowledge::config_service::KnowledgeConfig;
+use knowledge::importer::{self, TableImportOptions};
+use knowledge::writer::{KnowledgeWriter, WriterConf};
+use knowledge::state::AppState;
+use knowledge::tasks::QueueConf;
+use knowledge::{repository, router, watcher, writer};
 use tower_http::cors::Any;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 use tracing::info;
@@ -36,17 +44,48 @@ async fn main() -> anyhow::Result<()> {
 
     info!("Start Knolwdge at {:?}", std::env::current_dir().unwrap());
 
-    //create app with routers
-    let app = create_app();
+    if let Some(command) = args.command {
+        return run_command(command, &args.repository, &args.collections);
+    }
 
-    info!(args.load, "load repository when start: ");
+    //the writer configuration is needed to load the repository
+    let config = match &args.config {
+        Some(path) => Some(KnowledgeConfig::load(path)?),
+        None => None,
+    };
+    let (writer_conf, queue_conf) = config
+        .as_ref()
+        .map(|config| (config.writer.clone(), config.queues.clone()))
+        .unwrap_or_default();
+    let state = AppState::new(
+        args.repository.as_str(),
+        args.collections.as_str(),
+        writer_conf,
+        queue_conf,
+    );
+
+    info!(args.load, "load repository and collections when start: ");
     if args.load {
-        let (code,msg) = router::load_index().await;
-        if code != StatusCode::OK{
-            return Err(anyhow::Error::msg(msg.as_str().to_string()));
+        //the failed collections are logged, the others are served
+        state.load_all();
+    }
+
+    if let Some(config) = config {
+        if config.writer.commit_every_secs > 0 {
+            let committing = state.clone();
+            tokio::spawn(writer::run_commit_timer(
+                config.writer.commit_every_secs,
+                move || committing.clone().commit_due(),
+            ));
+        }
+        if let Some(watch) = config.watch {
+            tokio::spawn(watcher::run(state.clone(), watch));
         }
     }
 
+    //create app with routers
+    let app = create_app(state);
+
     //start http server
     let http_service_url = format!("{}:{}", args.host, args.port);
     let listener = tokio::net::TcpListener::bind(http_service_url)
@@ -58,25 +97,97 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn create_app() -> Router {
+/// Max size of the file uploaded to import
+const IMPORT_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+/// Run the command line command on a collection
+///
+/// `default_dir` and `collections_dir` are the directories of the default and the named collections,
+/// the collection is found in them like the server does, following its alias
+fn run_command(command: KnowledgeCommand, default_dir: &str, collections_dir: &str) -> anyhow::Result<()> {
+    let state = AppState::new(
+        default_dir,
+        collections_dir,
+        WriterConf::default(),
+        QueueConf::default(),
+    );
+    match command {
+        KnowledgeCommand::Import {
+            file,
+            collection,
+            format,
+            encoding,
+            has_header,
+            columns,
+        } => {
+            let (index, reader) = repository::load_index(&state.index_path(&collection)?)?;
+            let writer = KnowledgeWriter::open(&index, reader, WriterConf::default())?;
+            let content = std::fs::read(&file)?;
+            let options = TableImportOptions {
+                format,
+                encoding,
+                has_header,
+                columns,
+            };
+            let report = importer::import_table(&index, &writer, &content, &options)?;
+            //commit what the commit policy has left
+            if writer.pending() > 0 {
+                writer.commit()?;
+            }
+            info!(
+                file = %file,
+                %collection,
+                accepted = report.accepted.len(),
+                rejected = report.rejected.len(),
+                "imported"
+            );
+            for rejected in &report.rejected {
+                info!(row = rejected.index, reason = %rejected.reason, "rejected");
+            }
+            Ok(())
+        }
+        KnowledgeCommand::Export { output, collection } => {
+            let (index, reader) = repository::load_index(&state.index_path(&collection)?)?;
+            let mut out = BufWriter::new(File::create(&output)?);
+            let exported = repository::export_ndjson(&index, &reader.searcher(), &mut out)?;
+            out.flush()?;
+            info!(exported, %output, %collection, "exported");
+            Ok(())
+        }
+        KnowledgeCommand::Restore {
+            snapshot,
+            collection,
+        } => {
+            state.restore_snapshot(&collection, Path::new(&snapshot))?;
+            info!(%snapshot, %collection, "restored");
+            Ok(())
+        }
+    }
+}
+
+fn create_app(state: AppState) -> Router {
     Router::new()
         .route("/v1", get(|| async { "Hello" }))
         .route(
             "/v1/knowledge/repository",
             put(router::load_index).post(router::create_index),
         )
+        .route("/v1/knowledge/repository/restore", post(router::restore_index))
+        .route("/v1/collections", get(router::list_collections))
         .route(
-            "/v1/knowledge/query_title_body",
-            post(router::find_document),
-        )
-        .route(
-            "/v1/knowledge/query_title",
-            post(router::find_document_by_title),
-        )
-        .route(
-            "/v1/knowledge/doc",
-            post(router::push_documents).delete(router::delete_document),
+            "/v1/collections/:name",
+            post(router::create_collection)
+                .put(router::load_collection)
+                .delete(router::drop_collection),
         )
+        .route("/v1/collections/:name/restore", post(router::restore_collection))
+        .route("/v1/search", post(router::federated_search))
+        .route("/v1/admin/queues", get(router::queue_stats))
+        .route("/v1/admin/trash", get(router::list_trash))
+        .route("/v1/admin/snapshot", post(router::snapshot_collection))
+        //the default collection
+        .nest("/v1/knowledge", collection_routes())
+        .nest("/v1/collections/:name", collection_routes())
         .layer(
             tower_http::cors::CorsLayer::new()
                 .allow_methods(Any)
@@ -94,4 +205,35 @@ fn create_app() -> Router {
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
         )
+        .with_state(state)
+}
+
+/// The document and query routes of a collection
+fn collection_routes() -> Router<AppState> {
+    Router::new()
+        .route("/query_title_body", post(router::find_document))
+        .route("/query_title", post(router::find_document_by_title))
+        .route("/msearch", post(router::multi_search))
+        .route("/count", post(router::count_document))
+        .route("/query_hybrid", post(router::find_document_hybrid))
+        .route("/retrieve", post(router::retrieve_passages))
+        .route("/duplicates", get(router::list_duplicates))
+        .route(
+            "/doc",
+            post(router::push_documents).delete(router::delete_document),
+        )
+        .route("/bulk", post(router::bulk_documents))
+        .route("/rebuild", post(router::rebuild_collection))
+        .route("/export", get(router::export_documents))
+        .route("/reindex", post(router::reindex_collection))
+        .route("/stats", get(router::index_stats))
+        .route("/commit", post(router::commit_changes))
+        .route(
+            "/import",
+            post(router::import_table).layer(DefaultBodyLimit::max(IMPORT_MAX_BYTES)),
+        )
+        .route(
+            "/import_markup",
+            post(router::import_markup).layer(DefaultBodyLimit::max(IMPORT_MAX_BYTES)),
+        )
 }
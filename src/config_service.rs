@@ -8,10 +8,22 @@ use clap::ArgMatches;
 use serde::Deserialize;
 use tracing::{info, instrument};
 
+use crate::tasks::QueueConf;
+use crate::writer::WriterConf;
+
 /// Root struct of configurations
 #[derive(Debug, Deserialize)]
 pub struct KnowledgeConfig {
     pub http_service: ServiceConf,
+    /// Keep the repository in sync with a directory, disabled if absent
+    #[serde(default)]
+    pub watch: Option<WatchConf>,
+    /// The memory budget, threads and commit policy of the index writer
+    #[serde(default)]
+    pub writer: WriterConf,
+    /// The limits of the blocking search and write queues
+    #[serde(default)]
+    pub queues: QueueConf,
 }
 /// Implementation of KnowledgeConfig 
 impl KnowledgeConfig {
@@ -63,6 +75,30 @@ impl ServiceConf {
     }
 }
 
+/// Struct containing configurations of the directory watcher
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchConf {
+    /// The directory of the Markdown and HTML documents
+    pub dir: String,
+    /// Seconds between two scans of the directory
+    #[serde(default = "WatchConf::default_interval_secs")]
+    pub interval_secs: u64,
+    /// Keep the original content of the files in the `source` field
+    #[serde(default)]
+    pub keep_source: bool,
+    /// Category of the documents in the directory
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Collection kept in sync, the default collection if absent
+    #[serde(default)]
+    pub collection: Option<String>,
+}
+
+impl WatchConf {
+    fn default_interval_secs() -> u64 {
+        10
+    }
+}
 
 #[cfg(test)]
 mod config_test {
@@ -71,12 +107,14 @@ mod config_test {
     #[test]
     fn load_conf_test() {
         println!("Running test @ {:?}", std::env::current_dir().unwrap());
-        let path = "configuration /config.toml";
+        let path = "configuration/config.toml";
         let cfg_result = KnowledgeConfig::load(path);
         let err = match cfg_result {
             Ok(conf) => {
                 println!("{:?}", conf);
                 assert_eq!(conf.http_service.port, 3000);
+                assert!(conf.watch.is_none());
+                assert_eq!(conf.writer.commit_every_docs, 1);
                 // assert_eq!(conf.cache.size, 100);
                 None
             }
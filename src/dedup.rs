@@ -0,0 +1,246 @@
+//! Find the duplicated documents by their fingerprints
+//!
+//! A document has two fingerprints of its title and body:
+//! the exact content hash, and the SimHash of its character 3-grams which differs in a few bits
+//! between near-duplicate texts, in Chinese as well as in English.
+//! The fingerprints are looked up by splitting the SimHash into `max_distance + 1` bands,
+//! two SimHashes within `max_distance` bits share at least one band.
+//!
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// What to do with a duplicated document on ingest
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupMode {
+    /// reject the document
+    Reject,
+    /// replace the indexed duplicate, keeping its id and tags
+    Merge,
+    /// add the document and report the duplicate
+    Flag,
+}
+
+#[derive(Debug, Clone)]
+pub struct DedupOptions {
+    pub mode: DedupMode,
+    /// max different bits of the SimHashes of near-duplicates, 0 for exact duplicates only
+    pub max_distance: u32,
+}
+impl DedupOptions {
+    pub fn default_max_distance() -> u32 {
+        6
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fingerprint {
+    pub hash: u64,
+    pub simhash: u64,
+}
+
+impl Fingerprint {
+    /// The fingerprint of the document, the whitespaces and the letter case are ignored
+    pub fn of(title: &str, body: &str) -> Self {
+        let chars: Vec<char> = title
+            .chars()
+            .chain(body.chars())
+            .filter(|c| !c.is_whitespace())
+            .flat_map(char::to_lowercase)
+            .collect();
+        let text: String = chars.iter().collect();
+        Self {
+            hash: fnv1a(text.as_bytes()),
+            simhash: simhash(&chars),
+        }
+    }
+
+    /// Number of different bits of the SimHashes
+    pub fn distance(&self, other: &Fingerprint) -> u32 {
+        (self.simhash ^ other.simhash).count_ones()
+    }
+}
+
+/// 64-bit FNV-1a, stable across the builds unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// SimHash of the character 3-grams
+fn simhash(chars: &[char]) -> u64 {
+    let mut weights = [0i32; 64];
+    let mut buf = [0u8; 12];
+    for gram in chars.windows(3.min(chars.len()).max(1)) {
+        let mut len = 0;
+        for c in gram {
+            len += c.encode_utf8(&mut buf[len..]).len();
+        }
+        let hash = fnv1a(&buf[..len]);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if (hash >> bit) & 1 == 1 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| **w > 0)
+        .fold(0, |simhash, (bit, _)| simhash | (1 << bit))
+}
+
+/// The fingerprints of a set of documents, each with the key of its document
+pub struct Fingerprints<K> {
+    max_distance: u32,
+    entries: Vec<(K, Fingerprint)>,
+    hashes: HashMap<u64, usize>,
+    /// band value -> entries, for each band
+    bands: Vec<HashMap<u64, Vec<usize>>>,
+}
+
+impl<K> Fingerprints<K> {
+    pub fn new(max_distance: u32) -> Self {
+        let max_distance = max_distance.min(63);
+        Self {
+            max_distance,
+            entries: vec![],
+            hashes: HashMap::new(),
+            bands: (0..=max_distance).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    pub fn insert(&mut self, key: K, fingerprint: Fingerprint) {
+        let entry = self.entries.len();
+        self.hashes.entry(fingerprint.hash).or_insert(entry);
+        for (band, value) in self.band_values(fingerprint.simhash).into_iter().enumerate() {
+            self.bands[band].entry(value).or_default().push(entry);
+        }
+        self.entries.push((key, fingerprint));
+    }
+
+    /// Find the duplicate of the fingerprint, the exact one first, then the nearest one
+    ///
+    /// # Returns
+    ///
+    /// The key of the duplicate and the distance of the SimHashes, 0 for the exact duplicate
+    pub fn find(&self, fingerprint: &Fingerprint) -> Option<(&K, u32)> {
+        if let Some(entry) = self.hashes.get(&fingerprint.hash) {
+            return Some((&self.entries[*entry].0, 0));
+        }
+        self.near(fingerprint)
+            .map(|entry| (entry, self.entries[entry].1.distance(fingerprint)))
+            .min_by_key(|(entry, distance)| (*distance, *entry))
+            .map(|(entry, distance)| (&self.entries[entry].0, distance))
+    }
+
+    /// The groups of duplicated documents, in the order of insertion
+    pub fn clusters(&self) -> Vec<Vec<&K>> {
+        let mut roots: Vec<usize> = (0..self.entries.len()).collect();
+        for (entry, (_, fingerprint)) in self.entries.iter().enumerate() {
+            let near: Vec<usize> = self.near(fingerprint).filter(|n| *n < entry).collect();
+            let exact = self.hashes.get(&fingerprint.hash).copied();
+            for other in near.into_iter().chain(exact) {
+                let (a, b) = (find_root(&mut roots, entry), find_root(&mut roots, other));
+                roots[a.max(b)] = a.min(b);
+            }
+        }
+        let mut clusters: Vec<Vec<&K>> = Vec::new();
+        let mut cluster_of: HashMap<usize, usize> = HashMap::new();
+        for entry in 0..self.entries.len() {
+            let root = find_root(&mut roots, entry);
+            let cluster = *cluster_of.entry(root).or_insert_with(|| {
+                clusters.push(vec![]);
+                clusters.len() - 1
+            });
+            clusters[cluster].push(&self.entries[entry].0);
+        }
+        clusters.retain(|c| c.len() > 1);
+        clusters
+    }
+
+    /// The entries within `max_distance` of the fingerprint
+    fn near<'a>(&'a self, fingerprint: &'a Fingerprint) -> impl Iterator<Item = usize> + 'a {
+        let mut candidates: Vec<usize> = self
+            .band_values(fingerprint.simhash)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(band, value)| self.bands[band].get(&value))
+            .flatten()
+            .copied()
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+            .into_iter()
+            .filter(move |c| self.entries[*c].1.distance(fingerprint) <= self.max_distance)
+    }
+
+    /// Split the SimHash into `max_distance + 1` bands of (almost) equal width
+    fn band_values(&self, simhash: u64) -> Vec<u64> {
+        let bands = self.bands.len() as u32;
+        (0..bands)
+            .map(|band| {
+                let start = 64 * band / bands;
+                let end = 64 * (band + 1) / bands;
+                let mask = if end - start == 64 {
+                    u64::MAX
+                } else {
+                    (1u64 << (end - start)) - 1
+                };
+                (simhash >> start) & mask
+            })
+            .collect()
+    }
+}
+
+fn find_root(roots: &mut [usize], mut entry: usize) -> usize {
+    while roots[entry] != entry {
+        roots[entry] = roots[roots[entry]];
+        entry = roots[entry];
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let a = Fingerprint::of("儿童感冒", "儿童感冒怎么办？多喝水，多休息，不要乱用药，注意观察体温变化。");
+        let b = Fingerprint::of("儿童感冒 ", "儿童感冒怎么办？ 多喝水，多休息，不要乱用药，注意观察体温变化。");
+        let c = Fingerprint::of("儿童感冒", "儿童感冒怎么办？多喝水，多休息，不要乱用药，注意观察体温的变化。");
+        let d = Fingerprint::of("老人骨折", "老人骨折以后要尽快去医院，拍片确认骨折的位置和程度。");
+        assert_eq!(a, b);
+        assert_ne!(a.hash, c.hash);
+        assert!(a.distance(&c) < a.distance(&d));
+        assert!(a.distance(&d) > 10);
+    }
+
+    #[test]
+    fn test_find_and_clusters() {
+        let mut fingerprints = Fingerprints::new(3);
+        let fp = |simhash: u64, hash: u64| Fingerprint { hash, simhash };
+        fingerprints.insert("a", fp(0b1111, 1));
+        fingerprints.insert("b", fp(u64::MAX, 2));
+        fingerprints.insert("c", fp(0b1000_0111, 3)); //2 bits from "a"
+        fingerprints.insert("d", fp(0xff00, 2)); //same content as "b"
+        fingerprints.insert("e", fp(0xf0f0_f0f0, 5));
+
+        assert_eq!(Some((&"b", 0)), fingerprints.find(&fp(0, 2)));
+        assert_eq!(Some((&"a", 1)), fingerprints.find(&fp(0b0111, 9)));
+        assert_eq!(None, fingerprints.find(&fp(0xf000_0000_0000_0000, 9)));
+
+        let clusters = fingerprints.clusters();
+        assert_eq!(vec![vec![&"a", &"c"], vec![&"b", &"d"]], clusters);
+    }
+}
@@ -0,0 +1,119 @@
+//! Merge the results of a query run on several collections
+//!
+//! The BM25 scores of two collections are not comparable, their statistics differ,
+//! so the scores are normalized per collection before the ranked lists are merged:
+//! by min-max into `[0, 1]`, or replaced by the reciprocal rank as in the hybrid search.
+//!
+
+use serde::{Deserialize, Serialize};
+
+use crate::repository::RRF_K;
+
+/// How the scores of each collection are made comparable
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    /// `(score - min) / (max - min)` of the collection, 1 if all scores are equal
+    #[default]
+    MinMax,
+    /// `1 / (k + rank)`, the scores are ignored
+    Rrf,
+}
+
+/// A hit of the federated search, tagged with its collection
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FederatedHit<T> {
+    pub collection: String,
+    #[serde(flatten)]
+    pub doc: T,
+    /// normalized score, comparable across the collections
+    pub score: f32,
+    /// score in its collection
+    pub raw_score: f32,
+}
+
+/// Merge the ranked lists of the collections into one
+///
+/// # Arguments
+///
+/// * `lists` - The hits of each collection with their score, best first
+/// * `normalization` - How the scores are made comparable
+/// * `num` - The maximum number of hits to return
+///
+/// # Returns
+///
+/// The best hits of all collections by normalized score, then by raw score,
+/// then in the order of the collections
+pub fn merge<T>(
+    lists: Vec<(String, Vec<(T, f32)>)>,
+    normalization: Normalization,
+    num: usize,
+) -> Vec<FederatedHit<T>> {
+    let mut hits: Vec<(usize, FederatedHit<T>)> = Vec::new();
+    for (order, (collection, list)) in lists.into_iter().enumerate() {
+        let (min, max) = list
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), (_, s)| (min.min(*s), max.max(*s)));
+        for (rank, (doc, raw_score)) in list.into_iter().enumerate() {
+            let score = match normalization {
+                Normalization::MinMax if max > min => (raw_score - min) / (max - min),
+                Normalization::MinMax => 1.0,
+                Normalization::Rrf => 1.0 / (RRF_K + rank + 1) as f32,
+            };
+            hits.push((
+                order,
+                FederatedHit {
+                    collection: collection.clone(),
+                    doc,
+                    score,
+                    raw_score,
+                },
+            ));
+        }
+    }
+    //stable, the hits of a collection keep their rank on ties
+    hits.sort_by(|(a_order, a), (b_order, b)| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| b.raw_score.total_cmp(&a.raw_score))
+            .then_with(|| a_order.cmp(b_order))
+    });
+    hits.into_iter().take(num).map(|(_, hit)| hit).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lists() -> Vec<(String, Vec<(&'static str, f32)>)> {
+        vec![
+            (
+                "health".to_string(),
+                vec![("h1", 12.0), ("h2", 9.0), ("h3", 6.0)],
+            ),
+            ("law".to_string(), vec![("l1", 2.0), ("l2", 1.5), ("l3", 1.0)]),
+            ("empty".to_string(), vec![]),
+        ]
+    }
+
+    #[test]
+    fn test_merge() {
+        let hits = merge(lists(), Normalization::MinMax, 4);
+        let docs: Vec<(&str, &str)> = hits.iter().map(|h| (h.collection.as_str(), h.doc)).collect();
+        //the low BM25 scores of law don't push its best hit down
+        assert_eq!(
+            vec![("health", "h1"), ("law", "l1"), ("health", "h2"), ("law", "l2")],
+            docs
+        );
+        assert_eq!((1.0, 12.0), (hits[0].score, hits[0].raw_score));
+        assert_eq!(0.5, hits[2].score);
+
+        let hits = merge(lists(), Normalization::Rrf, 10);
+        assert_eq!(6, hits.len());
+        let docs: Vec<&str> = hits.iter().map(|h| h.doc).collect();
+        assert_eq!(vec!["h1", "l1", "h2", "l2", "h3", "l3"], docs);
+
+        let single = vec![("law".to_string(), vec![("l1", 3.0)])];
+        assert_eq!(1.0, merge(single, Normalization::MinMax, 10)[0].score);
+    }
+}
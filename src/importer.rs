@@ -0,0 +1,569 @@
+//! Import documents from files exported by other tools
+//!
+//! CSV and TSV files are mapped column by column to the document fields,
+//! the rows are fed into `repository::add_doc_in_batch`.
+//! Markdown and HTML files are stripped into plain text, one file per document.
+//!
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use clap::ValueEnum;
+use encoding_rs::{GBK, UTF_8};
+use pulldown_cmark::{Event, Parser as MarkdownParser, Tag};
+use scraper::{ElementRef, Html, Node, Selector};
+use serde::Deserialize;
+use tantivy::Index;
+use tracing::debug;
+
+use crate::repository::{self, IngestReport, KnownledgeDocument, RejectedDocument};
+use crate::writer::KnowledgeWriter;
+
+/// The document fields which can be mapped from a column
+const DOC_FIELDS: [&str; 4] = ["title", "body", "category", "tags"];
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TableFormat {
+    #[default]
+    Csv,
+    Tsv,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TextEncoding {
+    /// UTF-8 if the content is valid UTF-8, otherwise GBK
+    #[default]
+    Auto,
+    Utf8,
+    Gbk,
+}
+
+/// Options to read a CSV or TSV table
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct TableImportOptions {
+    #[serde(default)]
+    pub format: TableFormat,
+    #[serde(default)]
+    pub encoding: TextEncoding,
+    /// whether the first row is the header, detected if absent
+    #[serde(default)]
+    pub has_header: Option<bool>,
+    /// column mapping, e.g. `title:标题,body:内容,tags:3`, a column is given by its header name or its index.
+    /// Without mapping the columns are named `title`, `body`, `category` and `tags` in the header,
+    /// or in this order if there is no header.
+    #[serde(default)]
+    pub columns: Option<String>,
+}
+
+/// Documents read from a table, with the row number of each document
+#[derive(Debug, Default)]
+pub struct TableRows {
+    pub docs: Vec<KnownledgeDocument>,
+    pub rows: Vec<usize>,
+    pub rejected: Vec<RejectedDocument>,
+}
+
+/// Import a CSV or TSV table into the repository
+///
+/// # Arguments
+///
+/// * `index` - The reference to the tantivy index
+/// * `writer` - The shared writer of the index
+/// * `content` - The raw content of the file
+/// * `options` - How to read the table
+///
+///  # Returns:
+///
+/// The report of the accepted and rejected rows, the index in the report is the row number (starts from 1),
+/// or error if the table can't be read or the documents can't be committed
+pub fn import_table(
+    index: &Index,
+    writer: &KnowledgeWriter,
+    content: &[u8],
+    options: &TableImportOptions,
+) -> anyhow::Result<IngestReport> {
+    let table = read_table(content, options)?;
+    debug!(
+        "import_table, rows: {}, rejected: {}",
+        table.docs.len(),
+        table.rejected.len()
+    );
+    let mut report = repository::add_doc_in_batch(index, writer, table.docs)?;
+    //position in the batch -> row number
+    for accepted in report.accepted.iter_mut() {
+        accepted.index = table.rows[accepted.index];
+    }
+    for rejected in report.rejected.iter_mut() {
+        rejected.index = table.rows[rejected.index];
+    }
+    report.rejected.extend(table.rejected);
+    report.rejected.sort_by_key(|r| r.index);
+    Ok(report)
+}
+
+/// Read the documents from a CSV or TSV table
+///
+/// A row without title and body, or which can't be parsed is rejected.
+pub fn read_table(content: &[u8], options: &TableImportOptions) -> anyhow::Result<TableRows> {
+    let text = decode(content, options.encoding)?;
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .delimiter(match options.format {
+            TableFormat::Csv => b',',
+            TableFormat::Tsv => b'\t',
+        })
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+
+    let mapping = parse_mapping(options.columns.as_deref())?;
+    let mut records = csv_reader.records();
+    let mut table = TableRows::default();
+
+    let first = match records.next() {
+        Some(first) => first?,
+        None => return Ok(table),
+    };
+    let has_header = options
+        .has_header
+        .unwrap_or_else(|| detect_header(&first, &mapping));
+    let columns = resolve_columns(&mapping, if has_header { Some(&first) } else { None })?;
+    let first = if has_header { None } else { Some(Ok(first)) };
+
+    for (i, record) in first.into_iter().chain(records).enumerate() {
+        let row = if has_header { i + 2 } else { i + 1 };
+        match record
+            .map_err(|e| e.to_string())
+            .and_then(|record| make_row_doc(&record, &columns))
+        {
+            Ok(doc) => {
+                table.docs.push(doc);
+                table.rows.push(row);
+            }
+            Err(reason) => table.rejected.push(RejectedDocument { index: row, reason }),
+        }
+    }
+    Ok(table)
+}
+
+/// Decode the content to UTF-8 text, the UTF-8 BOM is removed
+fn decode(content: &[u8], encoding: TextEncoding) -> anyhow::Result<String> {
+    let (text, had_errors) = match encoding {
+        TextEncoding::Utf8 => UTF_8.decode_with_bom_removal(content),
+        TextEncoding::Gbk => GBK.decode_without_bom_handling(content),
+        TextEncoding::Auto => match UTF_8.decode_with_bom_removal(content) {
+            (text, false) => (text, false),
+            (_, true) => GBK.decode_without_bom_handling(content),
+        },
+    };
+    if had_errors {
+        return Err(anyhow::Error::msg(format!(
+            "content is not valid {:?} text",
+            encoding
+        )));
+    }
+    Ok(text.into_owned())
+}
+
+/// The column of a field, by header name or by index
+#[derive(Debug, Clone, PartialEq)]
+enum Column {
+    Name(String),
+    Index(usize),
+}
+
+/// Parse `field:column,field:column`, the default mapping is used if absent
+fn parse_mapping(columns: Option<&str>) -> anyhow::Result<HashMap<String, Column>> {
+    let Some(columns) = columns.filter(|c| !c.trim().is_empty()) else {
+        return Ok(HashMap::new());
+    };
+    let mut mapping = HashMap::new();
+    for pair in columns.split(',') {
+        let (field, column) = pair
+            .split_once(':')
+            .ok_or_else(|| anyhow::Error::msg(format!("invalid column mapping: {}", pair)))?;
+        let field = field.trim();
+        if !DOC_FIELDS.contains(&field) {
+            return Err(anyhow::Error::msg(format!("unknown field: {}", field)));
+        }
+        let column = column.trim();
+        let column = match column.parse::<usize>() {
+            Ok(i) => Column::Index(i),
+            Err(_) => Column::Name(column.to_string()),
+        };
+        mapping.insert(field.to_string(), column);
+    }
+    Ok(mapping)
+}
+
+/// The first row is the header if it contains all the mapped column names,
+/// or, without mapping by name, if it contains any of the field names.
+fn detect_header(first: &csv::StringRecord, mapping: &HashMap<String, Column>) -> bool {
+    let cells: Vec<&str> = first.iter().map(str::trim).collect();
+    let names: Vec<&String> = mapping
+        .values()
+        .filter_map(|c| match c {
+            Column::Name(name) => Some(name),
+            Column::Index(_) => None,
+        })
+        .collect();
+    if names.is_empty() {
+        cells
+            .iter()
+            .any(|cell| DOC_FIELDS.contains(&&*cell.to_lowercase()))
+    } else {
+        names.iter().all(|name| cells.contains(&name.as_str()))
+    }
+}
+
+/// Resolve the column index of each field
+fn resolve_columns(
+    mapping: &HashMap<String, Column>,
+    header: Option<&csv::StringRecord>,
+) -> anyhow::Result<HashMap<String, usize>> {
+    let position = |name: &str| {
+        header.and_then(|h| {
+            h.iter()
+                .position(|cell| cell.trim().eq_ignore_ascii_case(name))
+        })
+    };
+    let mut columns = HashMap::new();
+    if mapping.is_empty() {
+        for (i, field) in DOC_FIELDS.iter().enumerate() {
+            let column = if header.is_some() { position(field) } else { Some(i) };
+            if let Some(column) = column {
+                columns.insert(field.to_string(), column);
+            }
+        }
+        return Ok(columns);
+    }
+    for (field, column) in mapping {
+        let column = match column {
+            Column::Index(i) => *i,
+            Column::Name(name) => position(name).ok_or_else(|| {
+                anyhow::Error::msg(format!("column {} of field {} not found", name, field))
+            })?,
+        };
+        columns.insert(field.clone(), column);
+    }
+    Ok(columns)
+}
+
+/// The trimmed cell of the field, empty if the field isn't mapped or the row is short
+fn cell<'a>(
+    record: &'a csv::StringRecord,
+    columns: &HashMap<String, usize>,
+    field: &str,
+) -> &'a str {
+    columns
+        .get(field)
+        .and_then(|i| record.get(*i))
+        .map(str::trim)
+        .unwrap_or_default()
+}
+
+fn make_row_doc(
+    record: &csv::StringRecord,
+    columns: &HashMap<String, usize>,
+) -> Result<KnownledgeDocument, String> {
+    let title = cell(record, columns, "title");
+    let body = cell(record, columns, "body");
+    if title.is_empty() && body.is_empty() {
+        return Err("both title and body are empty".to_string());
+    }
+    let category = Some(cell(record, columns, "category"))
+        .filter(|c| !c.is_empty())
+        .map(str::to_string);
+    let tags = cell(record, columns, "tags")
+        .split([',', '，', ';', '；', '|'])
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok(KnownledgeDocument::new(
+        title.to_string(),
+        body.to_string(),
+        category,
+        tags,
+    ))
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MarkupFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+impl MarkupFormat {
+    /// Guess the format from the file extension, `None` if it's neither Markdown nor HTML
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "md" | "markdown" => Some(Self::Markdown),
+            "html" | "htm" => Some(Self::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Options to read a Markdown or HTML document
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct MarkupImportOptions {
+    #[serde(default)]
+    pub format: MarkupFormat,
+    #[serde(default)]
+    pub encoding: TextEncoding,
+    /// keep the original content in the `source` field
+    #[serde(default)]
+    pub keep_source: bool,
+    /// title used when the document has neither heading nor `<title>`, e.g. the file name
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    /// comma separated tags
+    #[serde(default)]
+    pub tags: Option<String>,
+}
+
+/// Import a Markdown or HTML document into the repository
+///
+/// # Arguments
+///
+/// * `index` - The reference to the tantivy index
+/// * `writer` - The shared writer of the index
+/// * `content` - The raw content of the file
+/// * `options` - How to read the document
+///
+///  # Returns:
+///
+/// The report of the document, or error if the content can't be decoded or the document can't be committed
+pub fn import_markup(
+    index: &Index,
+    writer: &KnowledgeWriter,
+    content: &[u8],
+    options: &MarkupImportOptions,
+) -> anyhow::Result<IngestReport> {
+    let doc = read_markup(content, options)?;
+    Ok(repository::add_doc_in_batch(index, writer, vec![doc])?)
+}
+
+/// Read a Markdown or HTML document
+///
+/// The title is the first heading (Markdown) or the `<title>` (HTML), falls back to `options.title`
+/// and then to the first line of the text. The body is the plain text without markup.
+pub fn read_markup(
+    content: &[u8],
+    options: &MarkupImportOptions,
+) -> anyhow::Result<KnownledgeDocument> {
+    let content = decode(content, options.encoding)?;
+    let (title, body) = match options.format {
+        MarkupFormat::Markdown => markdown_to_text(&content),
+        MarkupFormat::Html => html_to_text(&content),
+    };
+    let title = title
+        .or_else(|| options.title.clone())
+        .or_else(|| body.lines().next().map(|line| line.chars().take(64).collect()))
+        .unwrap_or_default();
+    if title.is_empty() && body.is_empty() {
+        return Err(anyhow::Error::msg("document is empty"));
+    }
+    let tags = options
+        .tags
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect();
+    let doc = KnownledgeDocument::new(title, body, options.category.clone(), tags);
+    Ok(if options.keep_source {
+        doc.with_source(content)
+    } else {
+        doc
+    })
+}
+
+/// Extract the first heading and the plain text from Markdown
+fn markdown_to_text(content: &str) -> (Option<String>, String) {
+    let mut title: Option<String> = None;
+    let mut heading: Option<String> = None;
+    let mut text = String::new();
+    for event in MarkdownParser::new(content) {
+        match event {
+            Event::Start(Tag::Heading(..)) => heading = Some(String::new()),
+            Event::End(Tag::Heading(..)) => {
+                let heading = heading.take().unwrap_or_default();
+                if title.is_none() && !heading.trim().is_empty() {
+                    title = Some(heading.trim().to_string());
+                }
+                push_line_break(&mut text);
+            }
+            Event::Text(t) | Event::Code(t) => {
+                if let Some(heading) = heading.as_mut() {
+                    heading.push_str(&t);
+                }
+                text.push_str(&t);
+            }
+            Event::Html(html) => text.push_str(&html_fragment_to_text(&html)),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::End(
+                Tag::Paragraph
+                | Tag::Item
+                | Tag::CodeBlock(_)
+                | Tag::BlockQuote
+                | Tag::TableRow
+                | Tag::TableHead,
+            ) => push_line_break(&mut text),
+            Event::End(Tag::TableCell) => text.push(' '),
+            _ => {}
+        }
+    }
+    (title, text.trim().to_string())
+}
+
+/// Extract the `<title>` (or the first `<h1>`) and the plain text of `<body>` from HTML
+fn html_to_text(content: &str) -> (Option<String>, String) {
+    let document = Html::parse_document(content);
+    let title = ["title", "h1"].iter().find_map(|tag| {
+        let selector = Selector::parse(tag).unwrap();
+        document
+            .select(&selector)
+            .next()
+            .map(|e| e.text().collect::<String>().trim().to_string())
+            .filter(|t| !t.is_empty())
+    });
+    let body_selector = Selector::parse("body").unwrap();
+    let root = document
+        .select(&body_selector)
+        .next()
+        .unwrap_or_else(|| document.root_element());
+    let mut text = String::new();
+    collect_text(root, &mut text);
+    (title, text.trim().to_string())
+}
+
+fn html_fragment_to_text(content: &str) -> String {
+    let fragment = Html::parse_fragment(content);
+    let mut text = String::new();
+    collect_text(fragment.root_element(), &mut text);
+    text
+}
+
+/// Elements whose content is not text
+const SKIPPED_TAGS: [&str; 5] = ["script", "style", "noscript", "template", "head"];
+/// Elements which start a new line
+const BLOCK_TAGS: [&str; 18] = [
+    "p", "div", "br", "li", "tr", "h1", "h2", "h3", "h4", "h5", "h6", "pre", "blockquote",
+    "section", "article", "table", "ul", "ol",
+];
+
+/// Collect the text of the element recursively, with line breaks around the block elements
+fn collect_text(element: ElementRef, text: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(t) => {
+                //collapse the whitespaces like a browser, without adding any between the text nodes
+                let mut after_space = text.is_empty() || text.ends_with('\n') || text.ends_with(' ');
+                for c in t.chars() {
+                    if !c.is_whitespace() {
+                        text.push(c);
+                        after_space = false;
+                    } else if !after_space {
+                        text.push(' ');
+                        after_space = true;
+                    }
+                }
+            }
+            Node::Element(e) if SKIPPED_TAGS.contains(&e.name()) => {}
+            Node::Element(e) => {
+                let block = BLOCK_TAGS.contains(&e.name());
+                if block {
+                    push_line_break(text);
+                }
+                if let Some(child_element) = ElementRef::wrap(child) {
+                    collect_text(child_element, text);
+                }
+                if block {
+                    push_line_break(text);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn push_line_break(text: &mut String) {
+    while text.ends_with(' ') {
+        text.pop();
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_csv_with_header() {
+        let content = "标题,内容,标签\n儿童感冒,多喝水,\"感冒,儿童\"\n,,\n老人感冒,多休息,感冒\n";
+        let options = TableImportOptions {
+            columns: Some("title:标题,body:内容,tags:标签".to_string()),
+            ..Default::default()
+        };
+        let table = read_table(content.as_bytes(), &options).unwrap();
+        assert_eq!(2, table.docs.len());
+        assert_eq!(vec![2, 4], table.rows);
+        assert_eq!(3, table.rejected[0].index);
+        let doc = serde_json::to_value(&table.docs[0]).unwrap();
+        assert_eq!("儿童感冒", doc["title"]);
+        assert_eq!(2, doc["tags"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn test_read_gbk_tsv_without_header() {
+        let (content, _, _) = GBK.encode("儿童感冒\t多喝水\t/健康/儿童\n");
+        let options = TableImportOptions {
+            format: TableFormat::Tsv,
+            ..Default::default()
+        };
+        let table = read_table(&content, &options).unwrap();
+        assert_eq!(1, table.docs.len());
+        assert_eq!(vec![1], table.rows);
+        let doc = serde_json::to_value(&table.docs[0]).unwrap();
+        assert_eq!("多喝水", doc["body"]);
+        assert_eq!("/健康/儿童", doc["category"]);
+    }
+
+    #[test]
+    fn test_read_markdown() {
+        let content = "# 儿童感冒\n\n多喝水，**多休息**。\n\n- 发烧\n- 咳嗽\n";
+        let options = MarkupImportOptions {
+            keep_source: true,
+            ..Default::default()
+        };
+        let doc = serde_json::to_value(read_markup(content.as_bytes(), &options).unwrap()).unwrap();
+        assert_eq!("儿童感冒", doc["title"]);
+        assert_eq!("儿童感冒\n多喝水，多休息。\n发烧\n咳嗽", doc["body"]);
+        assert_eq!(content, doc["source"]);
+    }
+
+    #[test]
+    fn test_read_html() {
+        let content = "<html><head><title>老人感冒</title><style>p {}</style></head>\
+            <body><h1>感冒</h1><p>多<b>休息</b></p><script>alert(1)</script></body></html>";
+        let options = MarkupImportOptions {
+            format: MarkupFormat::Html,
+            ..Default::default()
+        };
+        let doc = serde_json::to_value(read_markup(content.as_bytes(), &options).unwrap()).unwrap();
+        assert_eq!("老人感冒", doc["title"]);
+        assert_eq!("感冒\n多休息", doc["body"]);
+        assert!(doc.get("source").is_none());
+    }
+}
@@ -1,4 +1,15 @@
 pub mod repository;
-// pub mod config_service;
+pub mod config_service;
 pub mod agrument;
-pub mod router;
\ No newline at end of file
+pub mod router;
+pub mod importer;
+pub mod watcher;
+pub mod passage;
+pub mod vector;
+pub mod dedup;
+pub mod writer;
+pub mod state;
+pub mod tasks;
+pub mod federation;
+pub mod snapshot;
+pub mod stats;
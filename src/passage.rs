@@ -0,0 +1,225 @@
+//! Split long documents into overlapping passages
+//!
+//! The text is cut at the paragraph and sentence boundaries, including the Chinese punctuation,
+//! then the sentences are packed into passages of at most `max_chars` characters.
+//!
+
+use serde::Deserialize;
+
+/// How to split the document body into passages
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChunkOptions {
+    /// max characters of a passage
+    #[serde(default = "ChunkOptions::default_max_chars")]
+    pub max_chars: usize,
+    /// characters repeated from the end of the previous passage, at sentence boundaries
+    #[serde(default = "ChunkOptions::default_overlap")]
+    pub overlap: usize,
+}
+impl ChunkOptions {
+    fn default_max_chars() -> usize {
+        500
+    }
+    fn default_overlap() -> usize {
+        50
+    }
+}
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        Self {
+            max_chars: Self::default_max_chars(),
+            overlap: Self::default_overlap(),
+        }
+    }
+}
+
+/// A passage of the text, `offset` and `len` are in characters
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passage {
+    pub offset: usize,
+    pub len: usize,
+    pub text: String,
+}
+
+/// The characters ending a sentence
+const SENTENCE_ENDS: [char; 10] = ['\n', '。', '！', '？', '；', '…', '.', '!', '?', ';'];
+
+/// Split the text into passages
+///
+/// A sentence longer than `max_chars` is cut at `max_chars`.
+/// Each passage but the first starts with the last sentences of the previous one,
+/// as long as they are not longer than `overlap`.
+pub fn split_passages(text: &str, options: &ChunkOptions) -> Vec<Passage> {
+    let chars: Vec<char> = text.chars().collect();
+    let max_chars = options.max_chars.max(1);
+    let units = sentence_units(&chars, max_chars);
+
+    let mut passages = Vec::new();
+    let mut first = 0;
+    while first < units.len() {
+        //pack the sentences
+        let start = units[first].0;
+        let mut next = first;
+        while next < units.len() && units[next].1 - start <= max_chars {
+            next += 1;
+        }
+        let end = units[next - 1].1;
+        let passage: String = chars[start..end].iter().collect();
+        if !passage.trim().is_empty() {
+            passages.push(Passage {
+                offset: start,
+                len: end - start,
+                text: passage,
+            });
+        }
+        if next >= units.len() {
+            break;
+        }
+        //start the next passage from the sentences in the overlap
+        first = (first + 1..next)
+            .find(|i| end - units[*i].0 <= options.overlap)
+            .unwrap_or(next);
+    }
+    passages
+}
+
+/// Cut the text into sentences as [start, end) char ranges, no longer than `max_chars`
+fn sentence_units(chars: &[char], max_chars: usize) -> Vec<(usize, usize)> {
+    let mut units = Vec::new();
+    let mut start = 0;
+    for (i, c) in chars.iter().enumerate() {
+        if SENTENCE_ENDS.contains(c) || i + 1 - start >= max_chars {
+            units.push((start, i + 1));
+            start = i + 1;
+        }
+    }
+    if start < chars.len() {
+        units.push((start, chars.len()));
+    }
+    units
+}
+
+/// The size limit of the text returned to the caller, e.g. the context of a prompt
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Budget {
+    /// max characters
+    pub max_chars: Option<usize>,
+    /// max tokens, estimated by `estimate_tokens`
+    pub max_tokens: Option<usize>,
+}
+
+/// Estimate the number of tokens of the text for an LLM prompt
+///
+/// A CJK character is about one token, other words are about four characters per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    let quarters: usize = text.chars().map(token_quarters).sum();
+    quarters.div_ceil(4)
+}
+
+/// The cost of a character in quarters of a token
+fn token_quarters(c: char) -> usize {
+    if c.is_whitespace() {
+        0
+    } else if c.is_ascii() {
+        1
+    } else {
+        4
+    }
+}
+
+impl Budget {
+    /// Take the longest prefix of the text within the remaining budget,
+    /// and charge the budget with it.
+    ///
+    /// # Returns
+    ///
+    /// The prefix, and whether the text is cut.
+    pub fn take(&mut self, text: &str) -> (String, bool) {
+        let max_quarters = self.max_tokens.map(|t| t * 4);
+        let mut chars = 0;
+        let mut quarters = 0;
+        let mut end = text.len();
+        for (i, c) in text.char_indices() {
+            let q = token_quarters(c);
+            if self.max_chars.is_some_and(|m| chars + 1 > m)
+                || max_quarters.is_some_and(|m| quarters + q > m)
+            {
+                end = i;
+                break;
+            }
+            chars += 1;
+            quarters += q;
+        }
+        if let Some(m) = self.max_chars.as_mut() {
+            *m -= chars;
+        }
+        if let Some(m) = self.max_tokens.as_mut() {
+            *m = m.saturating_sub(quarters.div_ceil(4));
+        }
+        (text[..end].to_string(), end < text.len())
+    }
+
+    /// Whether nothing more fits in the budget
+    pub fn is_exhausted(&self) -> bool {
+        self.max_chars == Some(0) || self.max_tokens == Some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_passages() {
+        let text = "儿童感冒怎么办？多喝水。多休息！\n不要乱用药。";
+        let options = ChunkOptions {
+            max_chars: 12,
+            overlap: 5,
+        };
+        let passages = split_passages(text, &options);
+        let texts: Vec<&str> = passages.iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(
+            vec!["儿童感冒怎么办？多喝水。", "多喝水。多休息！\n", "多休息！\n不要乱用药。"],
+            texts
+        );
+        assert_eq!(8, passages[1].offset);
+        let chars: Vec<char> = text.chars().collect();
+        for p in &passages {
+            let expected: String = chars[p.offset..p.offset + p.len].iter().collect();
+            assert_eq!(expected, p.text);
+        }
+    }
+
+    #[test]
+    fn test_split_long_sentence() {
+        let text = "一二三四五六七八九十";
+        let options = ChunkOptions {
+            max_chars: 4,
+            overlap: 0,
+        };
+        let passages = split_passages(text, &options);
+        let texts: Vec<&str> = passages.iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(vec!["一二三四", "五六七八", "九十"], texts);
+    }
+
+    #[test]
+    fn test_budget() {
+        assert_eq!(2, estimate_tokens("感冒"));
+        assert_eq!(2, estimate_tokens("cold flu"));
+
+        let mut budget = Budget {
+            max_chars: Some(5),
+            max_tokens: None,
+        };
+        assert_eq!(("多喝水。".to_string(), false), budget.take("多喝水。"));
+        assert_eq!(("多".to_string(), true), budget.take("多休息！"));
+        assert!(budget.is_exhausted());
+
+        let mut budget = Budget {
+            max_chars: None,
+            max_tokens: Some(3),
+        };
+        assert_eq!(("ab 儿童".to_string(), true), budget.take("ab 儿童感冒"));
+        assert!(budget.is_exhausted());
+    }
+}
@@ -7,45 +7,538 @@
 
 
 use cang_jie::{CangJieTokenizer, CANG_JIE};
+use crate::passage::{self, Budget, ChunkOptions};
+use crate::dedup::{DedupMode, DedupOptions, Fingerprint, Fingerprints};
+use crate::federation::FederatedHit;
+use crate::snapshot::SnapshotInfo;
+use crate::stats::IndexStats;
+use crate::vector::VectorIndex;
+use crate::writer::{CommitInfo, KnowledgeWriter};
 use chrono::Local;
 use serde::Deserialize;
+use serde::ser::SerializeMap;
 use serde::Serialize;
+use serde::Serializer;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use tantivy::aggregation::agg_req::Aggregations;
+use tantivy::aggregation::AggregationCollector;
+use tantivy::aggregation::AggregationLimits;
+use tantivy::collector::Count;
+use tantivy::collector::FacetCollector;
+use tantivy::collector::FacetCounts;
+use tantivy::collector::MultiCollector;
 use tantivy::collector::TopDocs;
 use tantivy::doc;
 use tantivy::query::BooleanQuery;
+use tantivy::query::ConstScorer;
+use tantivy::query::EnableScoring;
+use tantivy::query::Explanation;
 use tantivy::query::Query;
 use tantivy::query::QueryParser;
+use tantivy::query::Scorer;
+use tantivy::query::TermQuery;
+use tantivy::query::Weight;
 use tantivy::query_grammar::Occur;
 use tantivy::schema::*;
 use tantivy::time::format_description::well_known::Rfc3339;
+use tantivy::time::OffsetDateTime;
+use tantivy::tokenizer::TokenStream;
+use tantivy::DateTime;
+use tantivy::DocAddress;
+use tantivy::DocId;
+use tantivy::DocSet;
 use tantivy::Index;
 use tantivy::IndexReader;
+use tantivy::IndexWriter;
 use tantivy::ReloadPolicy;
+use tantivy::Score;
 use tantivy::Searcher;
+use tantivy::SegmentId;
+use tantivy::SegmentReader;
 use tantivy::TantivyError;
+use tantivy::TERMINATED;
 use tracing::debug;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct KnownledgeDocument {
     #[serde(default)]
     title: String,
     #[serde(default)]
     body: String,
+    /// Hierarchical category path, e.g. `/health/children`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    /// Tags of the document, each tag may be a hierarchical path as well
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// Original Markdown or HTML source, stored for rendering but not indexed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    /// Unique key of the document, e.g. the file path of a watched file.
+    /// Adding a document replaces the one with the same id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    /// Id of the document this passage is split from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+    /// Char offset of this passage in the body of the parent document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    offset: Option<u64>,
+    /// Embedding computed by the client, kept in the vector index by the id of the document.
+    /// It's not stored in tantivy and never returned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    embedding: Option<Vec<f32>>,
+}
+impl KnownledgeDocument {
+    pub fn new(title: String, body: String, category: Option<String>, tags: Vec<String>) -> Self {
+        Self {
+            title,
+            body,
+            category,
+            tags,
+            source: None,
+            id: None,
+            parent: None,
+            offset: None,
+            embedding: None,
+        }
+    }
+
+    /// Set the unique key of the document
+    pub fn with_id(mut self, id: String) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Set the embedding of the document, see `add_doc_with_vectors`
+    pub fn with_embedding(mut self, embedding: Vec<f32>) -> Self {
+        self.embedding = Some(embedding);
+        self
+    }
+
+    /// Split the document into passages linked to it by its id, which is required
+    ///
+    /// The passages keep the title, category and tags of the document,
+    /// their ids are `{id}#{n}` with `n` starting from 0.
+    pub fn split(&self, options: &ChunkOptions) -> tantivy::Result<Vec<KnownledgeDocument>> {
+        let parent = self.id.as_ref().ok_or_else(|| {
+            TantivyError::InvalidArgument("id is required to split the document".to_string())
+        })?;
+        let passages = passage::split_passages(&self.body, options)
+            .into_iter()
+            .enumerate()
+            .map(|(n, p)| KnownledgeDocument {
+                title: self.title.clone(),
+                body: p.text,
+                category: self.category.clone(),
+                tags: self.tags.clone(),
+                source: None,
+                id: Some(format!("{}#{}", parent, n)),
+                parent: Some(parent.clone()),
+                offset: Some(p.offset as u64),
+                embedding: None,
+            })
+            .collect();
+        Ok(passages)
+    }
+
+    /// Keep the original source of the document
+    pub fn with_source(mut self, source: String) -> Self {
+        self.source = Some(source);
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeCountResult {
+    SUCCESS(usize),
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeIngestResult {
+    SUCCESS(IngestReport),
+    Failed(String),
+}
+
+/// Outcome of each document of a batch, identified by its position in the batch
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IngestReport {
+    pub accepted: Vec<AcceptedDocument>,
+    pub rejected: Vec<RejectedDocument>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AcceptedDocument {
+    pub index: usize,
+    pub create_at: String,
+    /// id, or title if it has no id, of the duplicate found on ingest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+}
+
+/// A document failed to be indexed, `index` is the position in the batch
+/// or the line number (starts from 1) in the bulk body
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RejectedDocument {
+    pub index: usize,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeBulkResult {
+    SUCCESS(BulkReport),
+    Failed(String),
+}
+
+/// Summary of a bulk ingestion, the accepted documents are only counted to keep the memory bounded
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BulkReport {
+    pub accepted: usize,
+    pub rejected: Vec<RejectedDocument>,
+    /// times the changes were committed
+    pub commits: usize,
+    /// the reason why the ingestion stopped before the end of the input
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum KnowledgeQueryResult {
     SUCCESS(Vec<KnownledgeDocumentWithTime>),
+    /// Returned instead of `SUCCESS` when search options (e.g. facets) are requested
+    Detailed(KnowledgeSearchOutput),
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeRetrieveResult {
+    SUCCESS(Vec<RetrievedPassage>),
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeCommitResult {
+    SUCCESS(CommitInfo),
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeSnapshotResult {
+    SUCCESS(SnapshotInfo),
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeStatsResult {
+    SUCCESS(IndexStats),
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeDuplicatesResult {
+    SUCCESS(Vec<DuplicateCluster>),
+    Failed(String),
+}
+
+/// A group of documents with the same or nearly the same title and body
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DuplicateCluster {
+    pub docs: Vec<KnownledgeDocumentWithTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeFederatedResult {
+    SUCCESS(Vec<FederatedHit<KnownledgeDocumentWithTime>>),
+    Failed(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KnowledgeHybridResult {
+    SUCCESS(Vec<HybridHit>),
     Failed(String),
 }
+
+/// A document found by the hybrid search, ranked by the fusion of its BM25 and vector ranks
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HybridHit {
+    #[serde(flatten)]
+    pub doc: KnownledgeDocumentWithTime,
+    /// reciprocal rank fusion score
+    pub score: f32,
+    /// rank in the BM25 results, from 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_rank: Option<usize>,
+    /// rank in the vector results, from 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector_rank: Option<usize>,
+}
+
+/// A passage retrieved for a question, with what's needed to cite it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetrievedPassage {
+    /// id of the document the passage comes from, the parent if it's split
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// id of the passage itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub title: String,
+    /// char offset of the text in the body of the source document
+    pub offset: u64,
+    /// length of the text in chars
+    pub len: usize,
+    pub text: String,
+    pub score: f32,
+    /// the text is cut to fit in the budget
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// The documents found by a search together with the extra results asked in `SearchOptions`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KnowledgeSearchOutput {
+    pub docs: Vec<KnownledgeDocumentWithTime>,
+    /// facet field name -> counts of the requested facet path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub facets: BTreeMap<String, Vec<FacetCount>>,
+    /// document counts per day or month
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub histogram: Vec<HistogramBucket>,
+    /// results of the requested aggregations, keyed by aggregation name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregations: Option<serde_json::Value>,
+    /// the matched passages grouped by their parent document, in place of `docs` if `group_by_parent` is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<ParentHit>,
+}
+impl KnowledgeSearchOutput {
+    fn empty() -> Self {
+        Self {
+            docs: vec![],
+            facets: BTreeMap::new(),
+            histogram: vec![],
+            aggregations: None,
+            groups: vec![],
+        }
+    }
+}
+
+/// A document found by its passages, with the best passage score as its score
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParentHit {
+    /// id of the parent document, or of the document itself if it's not split
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    pub title: String,
+    pub score: f32,
+    /// the matched passages, best first
+    pub passages: Vec<PassageHit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PassageHit {
+    #[serde(flatten)]
+    pub doc: KnownledgeDocumentWithTime,
+    pub score: f32,
+}
+
+/// Count of documents under a facet, with the counts of its children
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FacetCount {
+    pub facet: String,
+    pub count: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<FacetCount>,
+}
+
+/// Request counts of a facet field, e.g. `{"field": "category", "path": "/", "depth": 2}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct FacetRequest {
+    /// facet field name, `category` or `tags`
+    pub field: String,
+    /// the facet whose children are counted, root by default
+    #[serde(default = "FacetRequest::default_path")]
+    pub path: String,
+    /// levels of children to count below `path`, 1 by default
+    #[serde(default = "FacetRequest::default_depth")]
+    pub depth: usize,
+}
+impl FacetRequest {
+    fn default_path() -> String {
+        "/".to_string()
+    }
+    fn default_depth() -> usize {
+        1
+    }
+}
+
+/// Interval of the date histogram buckets
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistogramInterval {
+    Day,
+    Month,
+}
+
+/// Request document counts per interval on a date fast field,
+/// e.g. `{"interval": "month"}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistogramRequest {
+    /// date fast field name, `create_at` by default
+    #[serde(default = "HistogramRequest::default_field")]
+    pub field: String,
+    pub interval: HistogramInterval,
+}
+impl HistogramRequest {
+    fn default_field() -> String {
+        "create_at".to_string()
+    }
+}
+
+/// Document count of a histogram interval, the key is `2024-01-31` for day and `2024-01` for month
 #[derive(Debug, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    pub key: String,
+    pub doc_count: u64,
+}
+
+/// The stored fields returned in the search results, all of them by default
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    title: bool,
+    body: bool,
+    create_at: bool,
+    category: bool,
+    tags: bool,
+    source: bool,
+    id: bool,
+    parent: bool,
+    offset: bool,
+}
+impl Default for Projection {
+    fn default() -> Self {
+        Self {
+            title: true,
+            body: true,
+            create_at: true,
+            category: true,
+            tags: true,
+            source: true,
+            id: true,
+            parent: true,
+            offset: true,
+        }
+    }
+}
+impl Projection {
+    /// Build projection from the field names, `None` selects all fields
+    pub fn from_fields(fields: Option<&Vec<String>>) -> tantivy::Result<Self> {
+        let Some(fields) = fields else {
+            return Ok(Self::default());
+        };
+        let mut projection = Self {
+            title: false,
+            body: false,
+            create_at: false,
+            category: false,
+            tags: false,
+            source: false,
+            id: false,
+            parent: false,
+            offset: false,
+        };
+        for field in fields {
+            match field.as_str() {
+                "title" => projection.title = true,
+                "body" => projection.body = true,
+                "create_at" => projection.create_at = true,
+                "category" => projection.category = true,
+                "tags" => projection.tags = true,
+                "source" => projection.source = true,
+                "id" => projection.id = true,
+                "parent" => projection.parent = true,
+                "offset" => projection.offset = true,
+                _ => return Err(TantivyError::FieldNotFound(field.to_string())),
+            }
+        }
+        Ok(projection)
+    }
+}
+
+/// Optional parts of a search besides the top documents
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct SearchOptions {
+    /// stored fields returned in the documents, e.g. `["title", "create_at"]`, all fields if absent
+    #[serde(default)]
+    pub fields: Option<Vec<String>>,
+    #[serde(default)]
+    pub facets: Vec<FacetRequest>,
+    #[serde(default)]
+    pub histogram: Option<HistogramRequest>,
+    /// aggregations in the tantivy (elasticsearch compatible) format, e.g.
+    /// `{"create_at_stats": {"stats": {"field": "create_at"}}}`
+    #[serde(default)]
+    pub aggregations: Option<Aggregations>,
+    /// group the matched passages by parent document, `limit` is then the number of the documents
+    #[serde(default)]
+    pub group_by_parent: bool,
+}
+impl SearchOptions {
+    /// Whether nothing more than the top documents is requested, the projection doesn't count
+    pub fn is_plain(&self) -> bool {
+        self.facets.is_empty()
+            && self.histogram.is_none()
+            && self.aggregations.is_none()
+            && !self.group_by_parent
+    }
+}
+#[derive(Debug, Deserialize)]
 pub struct KnownledgeDocumentWithTime {
     #[serde(flatten)]
     doc: KnownledgeDocument,
     #[serde(default)]
     create_at: String,
+    /// the fields serialized, title, body and create_at are kept even if empty when selected
+    #[serde(skip_deserializing)]
+    projection: Projection,
+}
+impl Serialize for KnownledgeDocumentWithTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let doc = &self.doc;
+        let mut map = serializer.serialize_map(None)?;
+        if self.projection.title {
+            map.serialize_entry("title", &doc.title)?;
+        }
+        if self.projection.body {
+            map.serialize_entry("body", &doc.body)?;
+        }
+        if let Some(category) = &doc.category {
+            map.serialize_entry("category", category)?;
+        }
+        if !doc.tags.is_empty() {
+            map.serialize_entry("tags", &doc.tags)?;
+        }
+        if let Some(source) = &doc.source {
+            map.serialize_entry("source", source)?;
+        }
+        if let Some(id) = &doc.id {
+            map.serialize_entry("id", id)?;
+        }
+        if let Some(parent) = &doc.parent {
+            map.serialize_entry("parent", parent)?;
+        }
+        if let Some(offset) = &doc.offset {
+            map.serialize_entry("offset", offset)?;
+        }
+        if self.projection.create_at {
+            map.serialize_entry("create_at", &self.create_at)?;
+        }
+        map.end()
+    }
 }
 impl KnownledgeDocumentWithTime {
     fn pick_text_field(
@@ -76,36 +569,125 @@ impl KnownledgeDocumentWithTime {
         }
     }
 
+    fn pick_facet_fields(retrieved_doc: &Document, f: Option<Field>) -> Vec<String> {
+        match f {
+            Some(f) => retrieved_doc
+                .get_all(f)
+                .filter_map(|v| match v {
+                    Value::Facet(facet) => Some(facet.to_path_string()),
+                    _ => None,
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn pick_optional_text_field(retrieved_doc: &Document, f: Option<Field>) -> Option<String> {
+        match retrieved_doc.get_first(f?) {
+            Some(Value::Str(text)) => Some(text.to_string()),
+            _ => None,
+        }
+    }
+
+    fn pick_optional_u64_field(retrieved_doc: &Document, f: Option<Field>) -> Option<u64> {
+        match retrieved_doc.get_first(f?) {
+            Some(Value::U64(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Build the result from the retrieved document, the fields not selected in `projection`
+    /// are left empty and omitted in the response.
     pub fn build_from_document(
         retrieved_doc: Document,
         title: &Field,
         body: &Field,
         create_at: &Field,
+        optional_fields: &OptionalFields,
+        projection: &Projection,
     ) -> tantivy::Result<Self> {
-        let title_str = Self::pick_text_field(&retrieved_doc, title, "title")?;
-        let body_str = Self::pick_text_field(&retrieved_doc, body, "body")?;
-        let create_at_str = Self::pick_date_field(&retrieved_doc, create_at, "create_at")?;
+        let title_str = if projection.title {
+            Self::pick_text_field(&retrieved_doc, title, "title")?
+        } else {
+            String::new()
+        };
+        let body_str = if projection.body {
+            Self::pick_text_field(&retrieved_doc, body, "body")?
+        } else {
+            String::new()
+        };
+        let create_at_str = if projection.create_at {
+            Self::pick_date_field(&retrieved_doc, create_at, "create_at")?
+        } else {
+            String::new()
+        };
+        let category = if projection.category {
+            Self::pick_facet_fields(&retrieved_doc, optional_fields.category)
+                .into_iter()
+                .next()
+        } else {
+            None
+        };
+        let tags = if projection.tags {
+            Self::pick_facet_fields(&retrieved_doc, optional_fields.tags)
+        } else {
+            vec![]
+        };
+        let source = if projection.source {
+            Self::pick_optional_text_field(&retrieved_doc, optional_fields.source)
+        } else {
+            None
+        };
+        let id = if projection.id {
+            Self::pick_optional_text_field(&retrieved_doc, optional_fields.id)
+        } else {
+            None
+        };
+        let parent = if projection.parent {
+            Self::pick_optional_text_field(&retrieved_doc, optional_fields.parent)
+        } else {
+            None
+        };
+        let offset = if projection.offset {
+            Self::pick_optional_u64_field(&retrieved_doc, optional_fields.offset)
+        } else {
+            None
+        };
         Ok(Self {
             doc: KnownledgeDocument {
                 title: title_str,
                 body: body_str,
+                category,
+                tags,
+                source,
+                id,
+                parent,
+                offset,
+                embedding: None,
             },
             create_at: create_at_str,
+            projection: *projection,
         })
     }
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize)]
 pub enum Combiner {
     AND,
     OR,
 }
 /// The function that will create tantivy index in the path.
-/// It will clear the path first, everything in the path will be removed.
+/// It fails if the path is a directory which is not empty, nothing is removed.
 ///
-/// The schema is solid which has three fields: title, body and create_at.
+/// The schema is solid which has eleven fields: title, body, create_at, category, tags, source, id, parent, offset,
+/// content_hash and simhash.
 /// `title` and `body` are Text fields in Chinese characters.
 /// `create_at` is a Date field which auto generated when create the document,
 /// which will be used when remove document.
+/// `category` and `tags` are Facet fields used to count the matched documents.
+/// `source` keeps the original Markdown or HTML, it's stored but not indexed.
+/// `id` is the optional unique key of the document, indexed as a whole.
+/// `parent` and `offset` link a passage to the document it's split from.
+/// `content_hash` and `simhash` are the fingerprints of title and body to find the duplicates.
 ///
 /// # Arguments
 ///
@@ -115,13 +697,23 @@ pub enum Combiner {
 ///
 /// (Index and Reader) or Error if index creation failed
 pub fn create_index(index_path: &str) -> tantivy::Result<(Index, IndexReader)> {
-    debug!(?index_path, "create_index");
-    let _ = fs::remove_dir_all(&*index_path); //ignore error if directory does not exist
-    fs::create_dir(&*index_path)?;
+    create_index_with(index_path, TextTokenizer::default())
+}
+/// Create new index like `create_index`, the title and body are tokenized by `tokenizer`
+pub fn create_index_with(index_path: &str, tokenizer: TextTokenizer) -> tantivy::Result<(Index, IndexReader)> {
+    debug!(?index_path, ?tokenizer, "create_index");
+    let path = Path::new(index_path);
+    if path.read_dir().is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(TantivyError::InvalidArgument(format!(
+            "{} is not empty, remove it first",
+            index_path
+        )));
+    }
+    fs::create_dir_all(path)?;
 
-    let schema = make_schema();
+    let schema = make_schema(tokenizer);
 
-    let index = Index::create_in_dir(&Path::new(&*(index_path)), schema.clone())?;
+    let index = Index::create_in_dir(path, schema)?;
     index
         .tokenizers()
         .register(CANG_JIE, CangJieTokenizer::default()); // Build cang-jie Tokenizer
@@ -156,13 +748,13 @@ pub fn load_index(index_path: &str) -> tantivy::Result<(Index, IndexReader)> {
 }
 /// Add a single new document to the repository
 ///
-/// It very cost to commit the changes to the repository,recomend to ues `add_doc_batch` method
+/// It's committed by the commit policy of the writer, recomend to ues `add_doc_in_batch` method
 /// unless it's sure there is only one document to be added.
 ///
 /// # Arguments
 ///
 /// * `index` - The reference to the tantivy index
-/// * `reader` - The global tantivy reader
+/// * `writer` - The shared writer of the index
 /// * `doc` - The document to be add
 ///
 ///  # Returns:
@@ -170,45 +762,467 @@ pub fn load_index(index_path: &str) -> tantivy::Result<(Index, IndexReader)> {
 /// The create time in string or error
 pub fn add_doc(
     index: &Index,
-    reader: &IndexReader,
+    writer: &KnowledgeWriter,
     doc: KnownledgeDocument,
 ) -> tantivy::Result<String> {
     debug!(?doc, "add_doc");
-    let mut index_writer = index.writer(50_000_000)?;
-
     let now = now();
-    let document = make_doc(index, &doc, &*now)?;
-    index_writer.add_document(document)?;
-    index_writer.commit()?;
-    reader.reload()?; //refresh the reader
+    write_doc(index, &writer.writer(), &doc, &now)?;
+    writer.changed(1)?;
     Ok(now)
 }
 /// Add a batch documents to the repository
 ///
+/// A document which can't be indexed is rejected and doesn't abort the others.
+/// The batch is committed by the commit policy of the writer.
+///
 /// # Arguments
 ///
 /// * `index` - The reference to the tantivy index
-/// * `reader` - The global tantivy reader
+/// * `writer` - The shared writer of the index
 /// * `docs` - The documents to be add
 ///
 ///  # Returns:
 ///
-/// () or error
+/// The report of accepted and rejected documents, or error if the batch can't be committed
 pub fn add_doc_in_batch(
     index: &Index,
-    reader: &IndexReader,
+    writer: &KnowledgeWriter,
     docs: Vec<KnownledgeDocument>,
-) -> tantivy::Result<()> {
+) -> tantivy::Result<IngestReport> {
     debug!("add_docs, num: {}", docs.len());
-    let mut index_writer = index.writer(50_000_000)?;
+    let index_writer = writer.writer();
+
+    let mut report = IngestReport::default();
+    for (i, doc) in docs.iter().enumerate() {
+        let now = now();
+        let added = write_doc(index, &index_writer, doc, &now);
+        match added {
+            Ok(_) => report.accepted.push(AcceptedDocument {
+                index: i,
+                create_at: now,
+                duplicate_of: None,
+            }),
+            Err(e) => report.rejected.push(RejectedDocument {
+                index: i,
+                reason: e.to_string(),
+            }),
+        }
+    }
+    drop(index_writer);
+    writer.changed(report.accepted.len())?;
+    Ok(report)
+}
+/// Add a batch documents to the repository like `add_doc_in_batch`, with their embeddings,
+/// and check them against the indexed documents and each other for duplicates if `dedup` is set.
+///
+/// The embeddings are added to the vector index by the document id and saved after the batch is committed,
+/// a document with an embedding but without id, or with an embedding of the wrong dimension, is rejected.
+/// The previous embedding of a document pushed again without one is removed.
+///
+/// A duplicated document is rejected, merged or flagged by `DedupMode`. Merging replaces the duplicate
+/// by the new document, which takes the id of the duplicate if it has none, and the tags of both.
+/// Only the duplicate is deleted, by its id or else its address in the index, once the new document
+/// is written; a duplicate without id added earlier in the same batch is kept.
+///
+/// # Arguments
+///
+/// * `index` - The reference to the tantivy index
+/// * `writer` - The shared writer of the index
+/// * `vectors` - The vector index of the repository
+/// * `docs` - The documents to be add
+/// * `dedup` - What to do with the duplicates, `None` to skip the check
+///
+///  # Returns:
+///
+/// The report of accepted and rejected documents, or error if the batch or the vectors can't be saved
+pub fn add_doc_with_vectors(
+    index: &Index,
+    writer: &KnowledgeWriter,
+    vectors: &mut VectorIndex,
+    docs: Vec<KnownledgeDocument>,
+    dedup: Option<&DedupOptions>,
+) -> tantivy::Result<IngestReport> {
+    debug!("add_doc_with_vectors, num: {}, dedup: {:?}", docs.len(), dedup);
+    let index_writer = writer.writer();
+    let searcher = writer.reader().searcher();
+    let optional_fields = get_optional_fields(index);
+    let mut fingerprints = match dedup {
+        Some(options) => Some(indexed_fingerprints(index, &searcher, options.max_distance)?),
+        None => None,
+    };
+    //the documents added in this batch, referred by `Duplicate::Added`
+    let mut added: Vec<Original> = Vec::new();
+
+    let mut report = IngestReport::default();
+    for (i, mut doc) in docs.into_iter().enumerate() {
+        let now = now();
+        let fingerprint = Fingerprint::of(&doc.title, &doc.body);
+        let duplicate = match fingerprints.as_ref().and_then(|f| f.find(&fingerprint)) {
+            Some((Duplicate::Indexed(doc_address), _)) => {
+                Some(Original::read(index, &searcher, *doc_address)?)
+            }
+            Some((Duplicate::Added(n), _)) => Some(added[*n].clone()),
+            None => None,
+        };
+        let mut duplicate_of = None;
+        //deleted once the document replacing it is written
+        let mut merged = None;
+        if let (Some(options), Some(original)) = (dedup, duplicate) {
+            match options.mode {
+                DedupMode::Reject => {
+                    report.rejected.push(RejectedDocument {
+                        index: i,
+                        reason: format!("duplicate of {}", original.label()),
+                    });
+                    continue;
+                }
+                DedupMode::Merge => {
+                    if doc.id.is_none() {
+                        doc.id = original.id.clone();
+                    }
+                    for tag in &original.tags {
+                        if !doc.tags.contains(tag) {
+                            doc.tags.push(tag.clone());
+                        }
+                    }
+                    merged = Some(original.clone());
+                }
+                DedupMode::Flag => {}
+            }
+            duplicate_of = Some(original.label());
+        }
+
+        let added_doc = match (&doc.embedding, &doc.id) {
+            (Some(_), None) => Err("id is required with the embedding".to_string()),
+            (Some(embedding), Some(_)) => vectors.check(embedding),
+            (None, _) => Ok(()),
+        }
+        .and_then(|_| write_doc(index, &index_writer, &doc, &now).map_err(|e| e.to_string()));
+        match added_doc {
+            Ok(_) => {
+                if let Some(original) = merged {
+                    match (optional_fields.id, &original.id, original.address) {
+                        (Some(field), Some(id), _) if doc.id.as_ref() != Some(id) => {
+                            index_writer.delete_term(Term::from_field_text(field, id));
+                            vectors.remove(id);
+                        }
+                        //the same id is already replaced by the write
+                        (Some(_), Some(_), _) => {}
+                        (_, _, Some((segment_id, doc_id))) => {
+                            index_writer.delete_query(Box::new(SegmentDocQuery { segment_id, doc_id }))?;
+                        }
+                        //added earlier in this batch without id, it can't be told apart
+                        _ => {}
+                    }
+                }
+                match (&doc.id, &doc.embedding) {
+                    (Some(id), Some(embedding)) => vectors.insert(id, embedding),
+                    (Some(id), None) => {
+                        vectors.remove(id);
+                    }
+                    _ => {}
+                }
+                if let Some(fingerprints) = fingerprints.as_mut() {
+                    fingerprints.insert(Duplicate::Added(added.len()), fingerprint);
+                    added.push(Original {
+                        id: doc.id,
+                        title: doc.title,
+                        tags: doc.tags,
+                        address: None,
+                    });
+                }
+                report.accepted.push(AcceptedDocument {
+                    index: i,
+                    create_at: now,
+                    duplicate_of,
+                })
+            }
+            Err(reason) => report.rejected.push(RejectedDocument { index: i, reason }),
+        }
+    }
+    drop(index_writer);
+    //the merged duplicates are deleted as well
+    writer.changed(report.accepted.len())?;
+    vectors.save()?;
+    Ok(report)
+}
+/// Where the duplicate of a document is
+#[derive(Debug, Clone, Copy)]
+enum Duplicate {
+    /// in the index before the batch
+    Indexed(DocAddress),
+    /// the n-th document added in the batch
+    Added(usize),
+}
+/// The duplicated document, as much as needed to report and merge it
+#[derive(Debug, Clone)]
+struct Original {
+    id: Option<String>,
+    title: String,
+    tags: Vec<String>,
+    /// The segment and doc id of the indexed document, to delete it when it has no id
+    address: Option<(SegmentId, DocId)>,
+}
+impl Original {
+    fn read(index: &Index, searcher: &Searcher, doc_address: DocAddress) -> tantivy::Result<Self> {
+        let (title, _, _) = get_fields(index)?;
+        let optional_fields = get_optional_fields(index);
+        let doc = searcher.doc(doc_address)?;
+        let title = KnownledgeDocumentWithTime::pick_text_field(&doc, &title, "title")?;
+        Ok(Self {
+            id: KnownledgeDocumentWithTime::pick_optional_text_field(&doc, optional_fields.id),
+            tags: KnownledgeDocumentWithTime::pick_facet_fields(&doc, optional_fields.tags),
+            address: Some((searcher.segment_reader(doc_address.segment_ord).segment_id(), doc_address.doc_id)),
+            title,
+        })
+    }
+
+    /// The id of the document, or its title if it has no id
+    fn label(&self) -> String {
+        self.id.clone().unwrap_or_else(|| self.title.clone())
+    }
+}
+/// Matches a single document of a segment, to delete a merged duplicate without id
+///
+/// The document is matched by the segment it was read from, it's kept if that segment is merged meanwhile.
+#[derive(Debug, Clone)]
+struct SegmentDocQuery {
+    segment_id: SegmentId,
+    doc_id: DocId,
+}
+impl Query for SegmentDocQuery {
+    fn weight(&self, _: EnableScoring<'_>) -> tantivy::Result<Box<dyn Weight>> {
+        Ok(Box::new(self.clone()))
+    }
+}
+impl Weight for SegmentDocQuery {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> tantivy::Result<Box<dyn Scorer>> {
+        let doc = if reader.segment_id() == self.segment_id {
+            self.doc_id
+        } else {
+            TERMINATED
+        };
+        Ok(Box::new(ConstScorer::new(SingleDocSet { doc }, boost)))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> tantivy::Result<Explanation> {
+        if reader.segment_id() == self.segment_id && doc == self.doc_id {
+            Ok(Explanation::new("SegmentDocQuery", 1.0))
+        } else {
+            Err(TantivyError::InvalidArgument(format!("Document #({}) does not match", doc)))
+        }
+    }
+}
+/// The docset of one document, or none if `doc` is `TERMINATED`
+struct SingleDocSet {
+    doc: DocId,
+}
+impl DocSet for SingleDocSet {
+    fn advance(&mut self) -> DocId {
+        self.doc = TERMINATED;
+        self.doc
+    }
+
+    fn doc(&self) -> DocId {
+        self.doc
+    }
+
+    fn size_hint(&self) -> u32 {
+        (self.doc != TERMINATED) as u32
+    }
+}
+/// The fingerprints of the live documents, read from the fast fields,
+/// or computed from the title and body for the index created before they were introduced
+fn indexed_fingerprints(
+    index: &Index,
+    searcher: &Searcher,
+    max_distance: u32,
+) -> tantivy::Result<Fingerprints<Duplicate>> {
+    let (title, body, _) = get_fields(index)?;
+    let mut fingerprints = Fingerprints::new(max_distance);
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        let fast_fields = segment_reader.fast_fields();
+        let columns = match (fast_fields.u64("content_hash"), fast_fields.u64("simhash")) {
+            (Ok(hashes), Ok(simhashes)) => Some((hashes, simhashes)),
+            _ => None,
+        };
+        for doc_id in segment_reader.doc_ids_alive() {
+            let doc_address = DocAddress::new(segment_ord as u32, doc_id);
+            let fingerprint = match &columns {
+                Some((hashes, simhashes)) => match (hashes.first(doc_id), simhashes.first(doc_id)) {
+                    (Some(hash), Some(simhash)) => Fingerprint { hash, simhash },
+                    _ => continue,
+                },
+                None => {
+                    let doc = searcher.doc(doc_address)?;
+                    Fingerprint::of(
+                        &KnownledgeDocumentWithTime::pick_text_field(&doc, &title, "title")?,
+                        &KnownledgeDocumentWithTime::pick_text_field(&doc, &body, "body")?,
+                    )
+                }
+            };
+            fingerprints.insert(Duplicate::Indexed(doc_address), fingerprint);
+        }
+    }
+    Ok(fingerprints)
+}
+/// List the groups of duplicated documents in the repository
+///
+/// # Arguments
+///
+/// * `index` - The tantivy index to query.
+/// * `searcher` - The searcher snapshot to query on.
+/// * `max_distance` - The max different bits of the SimHashes of near-duplicates, 0 for exact duplicates only.
+///
+/// # Returns
+///
+/// The clusters of at least two documents, with their id, title and create time.
+pub fn duplicate_clusters(
+    index: &Index,
+    searcher: &Searcher,
+    max_distance: u32,
+) -> tantivy::Result<Vec<DuplicateCluster>> {
+    let fields = ["title", "id", "create_at"].iter().map(|f| f.to_string()).collect();
+    let projection = Projection::from_fields(Some(&fields))?;
+    let fingerprints = indexed_fingerprints(index, searcher, max_distance)?;
+    let mut clusters = Vec::new();
+    for cluster in fingerprints.clusters() {
+        let top_docs: Vec<(f32, DocAddress)> = cluster
+            .into_iter()
+            .filter_map(|key| match key {
+                Duplicate::Indexed(doc_address) => Some((0.0, *doc_address)),
+                Duplicate::Added(_) => None,
+            })
+            .collect();
+        let docs = build_results(index, searcher, top_docs, &projection)?;
+        clusters.push(DuplicateCluster { docs });
+    }
+    Ok(clusters)
+}
+/// Add a batch documents to the repository as passages
+///
+/// Each document is split into overlapping passages by `KnownledgeDocument::split`,
+/// the passages are indexed in place of the document and linked to it by the `parent` field.
+/// The passages previously split from a document with the same id are replaced.
+///
+/// # Arguments
+///
+/// * `index` - The reference to the tantivy index
+/// * `writer` - The shared writer of the index
+/// * `docs` - The documents to be split and add, their ids are required
+/// * `options` - How to split the documents
+///
+///  # Returns:
+///
+/// The report of accepted and rejected documents, or error if the batch can't be committed
+pub fn add_passages_in_batch(
+    index: &Index,
+    writer: &KnowledgeWriter,
+    docs: Vec<KnownledgeDocument>,
+    options: &ChunkOptions,
+) -> tantivy::Result<IngestReport> {
+    debug!("add_passages, num: {}, {:?}", docs.len(), options);
+    let optional_fields = get_optional_fields(index);
+    let (Some(id), Some(parent)) = (optional_fields.id, optional_fields.parent) else {
+        return Err(TantivyError::FieldNotFound("parent".to_string()));
+    };
+    let index_writer = writer.writer();
 
-    for doc in docs {
+    let mut report = IngestReport::default();
+    for (i, doc) in docs.iter().enumerate() {
         let now = now();
-        let document = make_doc(index, &doc, &*now)?;
-        index_writer.add_document(document)?;
+        let documents = doc.split(options).and_then(|passages| {
+            passages
+                .iter()
+                .map(|p| make_doc(index, p, &now))
+                .collect::<tantivy::Result<Vec<Document>>>()
+        });
+        let added = documents.and_then(|documents| {
+            //the document has an id, checked by `split`
+            let key = doc.id.as_deref().unwrap_or_default();
+            index_writer.delete_term(Term::from_field_text(id, key));
+            index_writer.delete_term(Term::from_field_text(parent, key));
+            for document in documents {
+                index_writer.add_document(document)?;
+            }
+            Ok(())
+        });
+        match added {
+            Ok(_) => report.accepted.push(AcceptedDocument {
+                index: i,
+                create_at: now,
+                duplicate_of: None,
+            }),
+            Err(e) => report.rejected.push(RejectedDocument {
+                index: i,
+                reason: e.to_string(),
+            }),
+        }
+    }
+    drop(index_writer);
+    writer.changed(report.accepted.len())?;
+    Ok(report)
+}
+/// Add the documents as they come from `docs`, committing every `commit_every` documents
+///
+/// The documents are not collected in memory, so it suits sources of arbitrary size,
+/// e.g. the lines of a NDJSON stream. The commits are forced whatever the commit policy of the writer.
+///
+/// # Arguments
+///
+/// * `index` - The reference to the tantivy index
+/// * `writer` - The shared writer of the index
+/// * `docs` - The line number and the parsed document, or the reason why the line can't be parsed
+/// * `commit_every` - Number of the accepted documents between two commits
+///
+///  # Returns:
+///
+/// The summary of the ingestion, or error if the changes can't be committed
+pub fn add_doc_stream<I>(
+    index: &Index,
+    writer: &KnowledgeWriter,
+    docs: I,
+    commit_every: usize,
+) -> tantivy::Result<BulkReport>
+where
+    I: Iterator<Item = (usize, Result<KnownledgeDocument, String>)>,
+{
+    debug!("add_doc_stream, commit every: {}", commit_every);
+    let mut report = BulkReport::default();
+    let mut uncommitted = 0;
+    for (line, doc) in docs {
+        let added = doc.and_then(|doc| {
+            let now = now();
+            write_doc(index, &writer.writer(), &doc, &now).map_err(|e| e.to_string())
+        });
+        match added {
+            Ok(_) => {
+                report.accepted += 1;
+                uncommitted += 1;
+            }
+            Err(reason) => report.rejected.push(RejectedDocument {
+                index: line,
+                reason,
+            }),
+        }
+        if uncommitted >= commit_every.max(1) {
+            commit_changes(writer, uncommitted)?;
+            report.commits += 1;
+            uncommitted = 0;
+        }
+    }
+    if uncommitted > 0 {
+        commit_changes(writer, uncommitted)?;
+        report.commits += 1;
+    }
+    Ok(report)
+}
+/// Record the changed documents and commit them, unless the commit policy just did
+fn commit_changes(writer: &KnowledgeWriter, docs: usize) -> tantivy::Result<()> {
+    if writer.changed(docs)?.is_none() {
+        writer.commit()?;
     }
-    index_writer.commit()?;
-    reader.reload()?; //refersh the reader;
     Ok(())
 }
 /// Query the documents for the given `keys` on Title and Body fields,
@@ -232,19 +1246,108 @@ pub fn query_title_body(
     op: Combiner,
     num: usize,
 ) -> tantivy::Result<Vec<KnownledgeDocumentWithTime>> {
+    let searcher = reader.searcher();
+    let output = search_title_body(index, &searcher, keys, op, num, &SearchOptions::default())?;
+    Ok(output.docs)
+}
+/// Query the documents for the given `keys` on Title and Body fields like `query_title_body`,
+/// and collect the extra results requested in `options` on the same matched documents.
+///
+/// # Arguments
+///
+/// * `index` - The tantivy index to query.
+/// * `searcher` - The searcher snapshot to query on, queries on the same searcher see the same documents.
+/// * `keys` - The search keys to query with.
+/// * `op` - The combiner to use for multiple keys.
+/// * `num` - The maximum number of results to return.
+/// * `options` - The extra results to collect, e.g. facet counts.
+///
+/// # Returns
+///
+/// The matched documents and the extra results.
+pub fn search_title_body(
+    index: &Index,
+    searcher: &Searcher,
+    keys: Vec<&str>,
+    op: Combiner,
+    num: usize,
+    options: &SearchOptions,
+) -> tantivy::Result<KnowledgeSearchOutput> {
     debug!("query_title_body, keys: {:?}, combiner:{:?}", keys, op);
-    if keys.len() == 0 {
-        return Ok(vec![]);
+    if keys.is_empty() {
+        return Ok(KnowledgeSearchOutput::empty());
     }
     // reader.reload()?; //reload in udpate APIs
-    let (title, body, create_at) = get_fields(&index)?;
-
-    let query_parser = QueryParser::for_index(&index, vec![title, body]);
-    let bool_query = build_bool_query(&query_parser, op, keys)?;
+    let bool_query = build_title_body_query(index, op, keys)?;
+    run_search(index, searcher, &bool_query, num, options)
+}
+/// Query the documents for the given `keys` on Title and Body fields, with their BM25 score
+///
+/// # Arguments
+///
+/// * `index` - The tantivy index to query.
+/// * `searcher` - The searcher snapshot to query on.
+/// * `keys` - The search keys to query with.
+/// * `op` - The combiner to use for multiple keys.
+/// * `num` - The maximum number of results to return.
+///
+/// # Returns
+///
+/// The matched documents with their score, best first.
+pub fn search_title_body_scored(
+    index: &Index,
+    searcher: &Searcher,
+    keys: Vec<&str>,
+    op: Combiner,
+    num: usize,
+) -> tantivy::Result<Vec<(KnownledgeDocumentWithTime, f32)>> {
+    debug!("search_title_body_scored, keys: {:?}, combiner:{:?}", keys, op);
+    if keys.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = build_title_body_query(index, op, keys)?;
+    let top_docs = searcher.search(&query, &TopDocs::with_limit(num))?;
+    let scores: Vec<f32> = top_docs.iter().map(|(score, _)| *score).collect();
+    let docs = build_results(index, searcher, top_docs, &Projection::default())?;
+    Ok(docs.into_iter().zip(scores).collect())
+}
+/// Count the documents for the given `keys` on Title and Body fields.
+///
+/// Only the `Count` collector runs, neither scores nor stored documents are loaded.
+///
+/// # Arguments
+///
+/// * `index` - The tantivy index to query.
+/// * `reader` - The global tantivy reader.
+/// * `keys` - The search keys to query with.
+/// * `op` - The combiner to use for multiple keys.
+///
+/// # Returns
+///
+/// The number of the matched documents.
+pub fn count_title_body(
+    index: &Index,
+    reader: &IndexReader,
+    keys: Vec<&str>,
+    op: Combiner,
+) -> tantivy::Result<usize> {
+    debug!("count_title_body, keys: {:?}, combiner:{:?}", keys, op);
+    if keys.is_empty() {
+        return Ok(0);
+    }
+    let bool_query = build_title_body_query(index, op, keys)?;
     let searcher = reader.searcher();
-    let top_docs: Vec<(f32, tantivy::DocAddress)> =
-        searcher.search(&bool_query, &TopDocs::with_limit(num))?;
-    build_results(&searcher, top_docs, num, &title, &body, &create_at)
+    searcher.search(&bool_query, &Count)
+}
+fn build_title_body_query(
+    index: &Index,
+    op: Combiner,
+    keys: Vec<&str>,
+) -> tantivy::Result<BooleanQuery> {
+    let (title, body, _) = get_fields(index)?;
+
+    let query_parser = QueryParser::for_index(index, vec![title, body]);
+    build_bool_query(&query_parser, op, keys)
 }
 /// Query the documents for the given `key` on Title
 /// Max `num` results.
@@ -265,25 +1368,429 @@ pub fn query_title(
     title_str: &str,
     num: usize,
 ) -> tantivy::Result<Vec<KnownledgeDocumentWithTime>> {
+    let searcher = reader.searcher();
+    let output = search_title(index, &searcher, title_str, num, &SearchOptions::default())?;
+    Ok(output.docs)
+}
+/// Query the documents for the given `key` on Title like `query_title`,
+/// and collect the extra results requested in `options` on the same matched documents.
+///
+/// # Arguments
+///
+/// * `index` - The tantivy index to query.
+/// * `searcher` - The searcher snapshot to query on, queries on the same searcher see the same documents.
+/// * `title_str` - The search key to query with.
+/// * `num` - The maximum number of results to return.
+/// * `options` - The extra results to collect, e.g. facet counts.
+///
+/// # Returns
+///
+/// The matched documents and the extra results.
+pub fn search_title(
+    index: &Index,
+    searcher: &Searcher,
+    title_str: &str,
+    num: usize,
+    options: &SearchOptions,
+) -> tantivy::Result<KnowledgeSearchOutput> {
     debug!("query_title, key: {:?}, ", title_str);
-    let (title, body, create_at) = get_fields(&index)?;
+    let (title, _, _) = get_fields(index)?;
 
-    let query_parser = QueryParser::for_index(&index, vec![title]);
+    let query_parser = QueryParser::for_index(index, vec![title]);
     let query = query_parser.parse_query(title_str)?;
+    run_search(index, searcher, &query, num, options)
+}
+/// Run the query with a `TopDocs` collector and, in the same pass, the collectors required by `options`
+fn run_search(
+    index: &Index,
+    searcher: &Searcher,
+    query: &dyn Query,
+    num: usize,
+    options: &SearchOptions,
+) -> tantivy::Result<KnowledgeSearchOutput> {
+    let mut projection = Projection::from_fields(options.fields.as_ref())?;
+    let limit = if options.group_by_parent {
+        //the parents need the title and the link
+        projection.title = true;
+        projection.id = true;
+        projection.parent = true;
+        num * GROUP_OVERSAMPLING
+    } else {
+        num
+    };
 
-    let searcher = reader.searcher();
-    let top_docs = searcher.search(&query, &TopDocs::with_limit(num))?;
-    build_results(&searcher, top_docs, num, &title, &body, &create_at)
+    let mut collectors = MultiCollector::new();
+    let top_docs_handle = collectors.add_collector(TopDocs::with_limit(limit.max(1)));
+    let mut facet_handles = Vec::with_capacity(options.facets.len());
+    for request in &options.facets {
+        let mut facet_collector = FacetCollector::for_field(&request.field);
+        facet_collector.add_facet(parse_facet(&request.path)?);
+        facet_handles.push(collectors.add_collector(facet_collector));
+    }
+    let histogram_handle = match &options.histogram {
+        Some(request) => Some(collectors.add_collector(AggregationCollector::from_aggs(
+            histogram_aggregations(request)?,
+            AggregationLimits::default(),
+        ))),
+        None => None,
+    };
+    let aggregations_handle = options.aggregations.as_ref().map(|aggs| {
+        collectors.add_collector(AggregationCollector::from_aggs(
+            aggs.clone(),
+            AggregationLimits::default(),
+        ))
+    });
+
+    let mut fruits = searcher.search(query, &collectors)?;
+    let top_docs = top_docs_handle.extract(&mut fruits);
+
+    let mut facets = BTreeMap::new();
+    for (request, handle) in options.facets.iter().zip(facet_handles) {
+        let counts = handle.extract(&mut fruits);
+        let mut nodes = facet_children(&counts, &parse_facet(&request.path)?);
+        expand_facets(searcher, query, &request.field, &mut nodes, request.depth)?;
+        facets.insert(request.field.clone(), nodes);
+    }
+    let histogram = match (&options.histogram, histogram_handle) {
+        (Some(request), Some(handle)) => {
+            histogram_buckets(to_json(handle.extract(&mut fruits))?, request.interval)
+        }
+        _ => vec![],
+    };
+    let aggregations = match aggregations_handle {
+        Some(handle) => Some(to_json(handle.extract(&mut fruits))?),
+        None => None,
+    };
+
+    let scores: Vec<f32> = top_docs.iter().map(|(score, _)| *score).collect();
+    let docs = build_results(index, searcher, top_docs, &projection)?;
+    let (docs, groups) = if options.group_by_parent {
+        let hits = docs
+            .into_iter()
+            .zip(scores)
+            .map(|(doc, score)| PassageHit { doc, score });
+        (vec![], group_passages(hits, num))
+    } else {
+        (docs, vec![])
+    };
+    Ok(KnowledgeSearchOutput {
+        docs,
+        facets,
+        histogram,
+        aggregations,
+        groups,
+    })
+}
+/// Times of the number of documents to fetch passages for, when grouping passages by parent
+const GROUP_OVERSAMPLING: usize = 5;
+/// Group the passage hits (best first) by their parent, the first `num` parents are kept
+pub fn group_passages(hits: impl Iterator<Item = PassageHit>, num: usize) -> Vec<ParentHit> {
+    let mut groups: Vec<ParentHit> = Vec::new();
+    for hit in hits {
+        let key = hit.doc.doc.parent.clone().or_else(|| hit.doc.doc.id.clone());
+        let group = match &key {
+            Some(key) => groups.iter_mut().find(|g| g.parent.as_ref() == Some(key)),
+            None => None,
+        };
+        match group {
+            Some(group) => group.passages.push(hit),
+            None => {
+                if groups.len() >= num {
+                    continue;
+                }
+                groups.push(ParentHit {
+                    parent: key,
+                    title: hit.doc.doc.title.clone(),
+                    score: hit.score,
+                    passages: vec![hit],
+                });
+            }
+        }
+    }
+    groups
+}
+/// The default constant `k` of the reciprocal rank fusion, a rank `r` scores `1 / (k + r)`
+pub const RRF_K: usize = 60;
+/// Times of the number of documents to fetch from each of the BM25 and vector results
+const HYBRID_OVERSAMPLING: usize = 4;
+/// How many documents a hybrid search returns and how its rankings are fused
+#[derive(Debug, Clone, Copy)]
+pub struct HybridOptions {
+    /// The maximum number of results to return
+    pub num: usize,
+    /// The constant of the reciprocal rank fusion, `RRF_K` by default
+    pub rrf_k: usize,
+}
+/// The fused score of a document, with its ranks in the BM25 and the vector results
+type FusedRanks = (f32, Option<usize>, Option<usize>);
+/// Query the documents with both the `keys` on Title and Body fields and the `vector` on the embeddings,
+/// and fuse the two rankings by reciprocal rank fusion.
+///
+/// Either of the keys and the vector may be empty, then it's a plain BM25 or vector search.
+/// The vectors of the documents no longer in the index are skipped.
+///
+/// # Arguments
+///
+/// * `index` - The tantivy index to query.
+/// * `searcher` - The searcher snapshot to query on.
+/// * `vectors` - The vector index of the repository.
+/// * `keys` - The search keys to query with.
+/// * `op` - The combiner to use for multiple keys.
+/// * `vector` - The embedding of the query, computed like the embeddings of the documents.
+/// * `options` - The number of results and the constant of the fusion.
+///
+/// # Returns
+///
+/// The documents with their fused score and ranks, the best first.
+pub fn search_hybrid(
+    index: &Index,
+    searcher: &Searcher,
+    vectors: &VectorIndex,
+    keys: Vec<&str>,
+    op: Combiner,
+    vector: Option<&[f32]>,
+    options: HybridOptions,
+) -> tantivy::Result<Vec<HybridHit>> {
+    debug!("search_hybrid, keys: {:?}, combiner:{:?}", keys, op);
+    let HybridOptions { num, rrf_k } = options;
+    let candidates = num.max(1) * HYBRID_OVERSAMPLING;
+    let text_hits: Vec<DocAddress> = if keys.is_empty() {
+        vec![]
+    } else {
+        let query = build_title_body_query(index, op, keys)?;
+        searcher
+            .search(&query, &TopDocs::with_limit(candidates))?
+            .into_iter()
+            .map(|(_, doc_address)| doc_address)
+            .collect()
+    };
+    let mut vector_hits: Vec<DocAddress> = Vec::new();
+    if let (Some(vector), Some(id_field)) = (vector, get_optional_fields(index).id) {
+        vectors.check(vector).map_err(TantivyError::InvalidArgument)?;
+        for (id, _) in vectors.search(vector, candidates) {
+            let query = TermQuery::new(
+                Term::from_field_text(id_field, &id),
+                IndexRecordOption::Basic,
+            );
+            if let Some((_, doc_address)) = searcher.search(&query, &TopDocs::with_limit(1))?.pop() {
+                vector_hits.push(doc_address);
+            }
+        }
+    }
+
+    //doc -> (score, text rank, vector rank)
+    let mut fused: HashMap<DocAddress, FusedRanks> = HashMap::new();
+    for (i, doc_address) in text_hits.into_iter().enumerate() {
+        let hit = fused.entry(doc_address).or_insert((0.0, None, None));
+        hit.0 += 1.0 / (rrf_k + i + 1) as f32;
+        hit.1 = Some(i + 1);
+    }
+    for (i, doc_address) in vector_hits.into_iter().enumerate() {
+        let hit = fused.entry(doc_address).or_insert((0.0, None, None));
+        hit.0 += 1.0 / (rrf_k + i + 1) as f32;
+        hit.2 = Some(i + 1);
+    }
+    let mut fused: Vec<(DocAddress, FusedRanks)> = fused.into_iter().collect();
+    fused.sort_by(|a, b| {
+        b.1 .0
+            .total_cmp(&a.1 .0)
+            .then_with(|| a.1 .1.unwrap_or(usize::MAX).cmp(&b.1 .1.unwrap_or(usize::MAX)))
+    });
+    fused.truncate(num);
+
+    let top_docs = fused.iter().map(|(doc_address, hit)| (hit.0, *doc_address)).collect();
+    let docs = build_results(index, searcher, top_docs, &Projection::default())?;
+    Ok(docs
+        .into_iter()
+        .zip(fused)
+        .map(|(doc, (_, (score, text_rank, vector_rank)))| HybridHit {
+            doc,
+            score,
+            text_rank,
+            vector_rank,
+        })
+        .collect())
 }
+/// Retrieve the best passages for a question, e.g. as the context of a RAG prompt
+///
+/// The question is cut into words by the tokenizer of the body, and scored like `query_title_body`
+/// with `Combiner::OR`. Only the best passage of each source document is kept,
+/// and the passages are taken in order of score until the budget is used up,
+/// the last one may be cut.
+///
+/// # Arguments
+///
+/// * `index` - The tantivy index to query.
+/// * `searcher` - The searcher snapshot to query on.
+/// * `question` - The question in natural language.
+/// * `num` - The maximum number of passages to return.
+/// * `budget` - The maximum size of the returned text.
+///
+/// # Returns
+///
+/// The passages, best first.
+pub fn retrieve_passages(
+    index: &Index,
+    searcher: &Searcher,
+    question: &str,
+    num: usize,
+    mut budget: Budget,
+) -> tantivy::Result<Vec<RetrievedPassage>> {
+    let keys = question_keys(index, question)?;
+    debug!("retrieve_passages, keys: {:?}", keys);
+    let options = SearchOptions {
+        fields: Some(
+            ["title", "body", "id", "parent", "offset"]
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+        ),
+        group_by_parent: true,
+        ..Default::default()
+    };
+    let output = search_title_body(
+        index,
+        searcher,
+        keys.iter().map(|k| k.as_str()).collect(),
+        Combiner::OR,
+        num,
+        &options,
+    )?;
+
+    let mut passages = Vec::with_capacity(output.groups.len());
+    for group in output.groups {
+        if budget.is_exhausted() {
+            break;
+        }
+        let best = match group.passages.into_iter().next() {
+            Some(best) => best,
+            None => continue,
+        };
+        let doc = best.doc.doc;
+        let (text, truncated) = budget.take(&doc.body);
+        if text.is_empty() {
+            break;
+        }
+        passages.push(RetrievedPassage {
+            source: group.parent,
+            id: doc.id,
+            title: doc.title,
+            offset: doc.offset.unwrap_or(0),
+            len: text.chars().count(),
+            text,
+            score: best.score,
+            truncated,
+        });
+    }
+    Ok(passages)
+}
+/// Cut the question into distinct words with the tokenizer of the body,
+/// the punctuation is dropped so that the words are safe for the query parser.
+fn question_keys(index: &Index, question: &str) -> tantivy::Result<Vec<String>> {
+    let (_, body, _) = get_fields(index)?;
+    let mut analyzer = index.tokenizer_for_field(body)?;
+    let mut stream = analyzer.token_stream(question);
+    let mut keys: Vec<String> = Vec::new();
+    while stream.advance() {
+        let word = stream.token().text.to_lowercase();
+        if !word.is_empty() && word.chars().all(char::is_alphanumeric) && !keys.contains(&word) {
+            keys.push(word);
+        }
+    }
+    Ok(keys)
+}
+/// Name of the date histogram aggregation built for `HistogramRequest`
+const HISTOGRAM_AGG: &str = "histogram";
+/// Build the daily date histogram aggregation, months are rolled up from the days
+/// because tantivy only supports `fixed_interval`.
+fn histogram_aggregations(request: &HistogramRequest) -> tantivy::Result<Aggregations> {
+    let aggs = serde_json::json!({
+        HISTOGRAM_AGG: {
+            "date_histogram": {
+                "field": request.field,
+                "fixed_interval": "1d",
+                "min_doc_count": 1
+            }
+        }
+    });
+    serde_json::from_value(aggs).map_err(|e| TantivyError::InvalidArgument(e.to_string()))
+}
+/// Read the day buckets out of the aggregation results, and merge them by `interval`
+fn histogram_buckets(results: serde_json::Value, interval: HistogramInterval) -> Vec<HistogramBucket> {
+    let key_len = match interval {
+        HistogramInterval::Day => "2024-01-31".len(),
+        HistogramInterval::Month => "2024-01".len(),
+    };
+    let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+    if let Some(buckets) = results[HISTOGRAM_AGG]["buckets"].as_array() {
+        for bucket in buckets {
+            let (Some(key), Some(doc_count)) = (
+                bucket["key_as_string"].as_str(),
+                bucket["doc_count"].as_u64(),
+            ) else {
+                continue;
+            };
+            let key = key.get(..key_len).unwrap_or(key);
+            *counts.entry(key.to_string()).or_default() += doc_count;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(key, doc_count)| HistogramBucket { key, doc_count })
+        .collect()
+}
+fn to_json<T: Serialize>(value: T) -> tantivy::Result<serde_json::Value> {
+    serde_json::to_value(value).map_err(|e| TantivyError::InternalError(e.to_string()))
+}
+/// The counted children of `parent` in `counts`
+fn facet_children(counts: &FacetCounts, parent: &Facet) -> Vec<FacetCount> {
+    counts
+        .get(parent.clone())
+        .map(|(facet, count)| FacetCount {
+            facet: facet.to_path_string(),
+            count,
+            children: vec![],
+        })
+        .collect()
+}
+/// Count the children of the `nodes` down to `depth` levels, one more search pass per level.
+///
+/// The `FacetCollector` only counts the direct children of the facets added into it,
+/// the first level has been counted alongside `TopDocs`.
+fn expand_facets(
+    searcher: &Searcher,
+    query: &dyn Query,
+    field_name: &str,
+    nodes: &mut [FacetCount],
+    depth: usize,
+) -> tantivy::Result<()> {
+    if depth <= 1 || nodes.is_empty() {
+        return Ok(());
+    }
+    let mut facet_collector = FacetCollector::for_field(field_name);
+    for node in nodes.iter() {
+        facet_collector.add_facet(parse_facet(&node.facet)?);
+    }
+    let counts = searcher.search(query, &facet_collector)?;
+    for node in nodes.iter_mut() {
+        node.children = facet_children(&counts, &parse_facet(&node.facet)?);
+        expand_facets(searcher, query, field_name, &mut node.children, depth - 1)?;
+    }
+    Ok(())
+}
+/// Build the results of the top documents with the fields selected in `projection`
+///
+/// The doc store of tantivy keeps each document as a whole in compressed blocks, so every stored field
+/// is still read and decompressed, the projection only makes the response smaller.
 fn build_results(
+    index: &Index,
     searcher: &Searcher,
     top_docs: Vec<(f32, tantivy::DocAddress)>,
-    num: usize,
-    title: &Field,
-    body: &Field,
-    create_at: &Field,
+    projection: &Projection,
 ) -> tantivy::Result<Vec<KnownledgeDocumentWithTime>> {
-    let mut result: Vec<KnownledgeDocumentWithTime> = Vec::with_capacity(num);
+    let (title, body, create_at) = get_fields(index)?;
+    let optional_fields = get_optional_fields(index);
+    let mut result: Vec<KnownledgeDocumentWithTime> = Vec::with_capacity(top_docs.len());
     for (_score, doc_address) in top_docs {
         let retrieved_doc = searcher.doc(doc_address)?;
         let res = KnownledgeDocumentWithTime::build_from_document(
@@ -291,6 +1798,8 @@ fn build_results(
             &title,
             &body,
             &create_at,
+            &optional_fields,
+            projection,
         )?;
         result.push(res);
     }
@@ -304,13 +1813,57 @@ fn get_fields(index: &Index) -> tantivy::Result<(Field, Field, Field)> {
 
     Ok((title, body, create_at))
 }
+/// The fields added to the schema after the first release,
+/// `None` for the index created before they were introduced
+#[derive(Debug, Clone, Copy)]
+pub struct OptionalFields {
+    pub category: Option<Field>,
+    pub tags: Option<Field>,
+    pub source: Option<Field>,
+    pub id: Option<Field>,
+    pub parent: Option<Field>,
+    pub offset: Option<Field>,
+    pub content_hash: Option<Field>,
+    pub simhash: Option<Field>,
+}
+fn get_optional_fields(index: &Index) -> OptionalFields {
+    let schema = index.schema();
+    OptionalFields {
+        category: schema.get_field("category").ok(),
+        tags: schema.get_field("tags").ok(),
+        source: schema.get_field("source").ok(),
+        id: schema.get_field("id").ok(),
+        parent: schema.get_field("parent").ok(),
+        offset: schema.get_field("offset").ok(),
+        content_hash: schema.get_field("content_hash").ok(),
+        simhash: schema.get_field("simhash").ok(),
+    }
+}
+/// Parse facet path, the leading `/` is optional, e.g. `health/children`
+fn parse_facet(path: &str) -> tantivy::Result<Facet> {
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    };
+    Facet::from_text(&path).map_err(|e| TantivyError::InvalidArgument(e.to_string()))
+}
+/// Parse the facet path of a document, which can't be the root
+fn parse_doc_facet(path: &str) -> tantivy::Result<Facet> {
+    let facet = parse_facet(path)?;
+    if facet.is_root() {
+        return Err(TantivyError::InvalidArgument(format!(
+            "empty facet path: {:?}",
+            path
+        )));
+    }
+    Ok(facet)
+}
 /// Delete all documents in the repository
-pub fn delele_all(index: &Index, reader: &IndexReader) -> tantivy::Result<()> {
+pub fn delele_all(writer: &KnowledgeWriter) -> tantivy::Result<()> {
     debug!("delete all");
-    let mut index_writer = index.writer(15_000_000)?;
-    index_writer.delete_all_documents()?;
-    index_writer.commit()?;
-    reader.reload()?;
+    writer.writer().delete_all_documents()?;
+    writer.changed(1)?;
     Ok(())
 }
 /// Delete a document from the repository based on its title and create timestamp.
@@ -318,7 +1871,7 @@ pub fn delele_all(index: &Index, reader: &IndexReader) -> tantivy::Result<()> {
 /// # Arguments
 ///
 /// * `index` - The reference to the tantivy index.
-/// * `reader` - The global tantivy reader.
+/// * `writer` - The shared writer of the index.
 /// * `title_key` - The title of the document to be deleted.
 /// * `ts` - The create timestamp of the document to be deleted. sample: 2023-12-22T12:58:00Z
 ///
@@ -327,7 +1880,7 @@ pub fn delele_all(index: &Index, reader: &IndexReader) -> tantivy::Result<()> {
 /// () or error
 pub fn delete(
     index: &Index,
-    reader: &IndexReader,
+    writer: &KnowledgeWriter,
     title_key: &str,
     ts: &str,
 ) -> tantivy::Result<()> {
@@ -340,12 +1893,245 @@ pub fn delete(
     let query_title = query_parser_title.parse_query(title_key)?;
     let bool_query = BooleanQuery::new(vec![(Occur::Must, query_ts), (Occur::Must, query_title)]);
     //delete
-    let mut index_writer = index.writer(15_000_000)?;
-    index_writer.delete_query(Box::new(bool_query))?;
-    index_writer.commit()?;
-    reader.reload()?;
+    writer.writer().delete_query(Box::new(bool_query))?;
+    writer.changed(1)?;
     Ok(())
 }
+/// Delete the documents by their ids, committed together by the commit policy of the writer
+///
+/// # Arguments
+///
+/// * `index` - The reference to the tantivy index.
+/// * `writer` - The shared writer of the index.
+/// * `ids` - The ids of the documents to be deleted.
+///
+/// # Returns
+///
+/// () or error
+pub fn delete_by_ids(index: &Index, writer: &KnowledgeWriter, ids: &[String]) -> tantivy::Result<()> {
+    debug!("delete ids, num: {}", ids.len());
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let id = get_optional_fields(index)
+        .id
+        .ok_or_else(|| TantivyError::FieldNotFound("id".to_string()))?;
+    let index_writer = writer.writer();
+    for key in ids {
+        index_writer.delete_term(Term::from_field_text(id, key));
+    }
+    drop(index_writer);
+    writer.changed(ids.len())?;
+    Ok(())
+}
+/// List the ids of the documents which have one, with their create time
+///
+/// # Arguments
+///
+/// * `index` - The reference to the tantivy index.
+/// * `reader` - The global tantivy reader.
+///
+/// # Returns
+///
+/// (id, create_at) of the documents, or error
+pub fn list_ids(index: &Index, reader: &IndexReader) -> tantivy::Result<Vec<(String, String)>> {
+    let (_, _, create_at) = get_fields(index)?;
+    let Some(id) = get_optional_fields(index).id else {
+        return Ok(vec![]);
+    };
+    let searcher = reader.searcher();
+    let mut ids = Vec::new();
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        for doc_id in segment_reader.doc_ids_alive() {
+            let retrieved_doc = searcher.doc(DocAddress::new(segment_ord as u32, doc_id))?;
+            if let Some(Value::Str(key)) = retrieved_doc.get_first(id) {
+                let ts = KnownledgeDocumentWithTime::pick_date_field(
+                    &retrieved_doc,
+                    &create_at,
+                    "create_at",
+                )?;
+                ids.push((key.to_string(), ts));
+            }
+        }
+    }
+    Ok(ids)
+}
+/// Number of the decompressed blocks of the doc store cached while exporting a segment
+const EXPORT_CACHE_BLOCKS: usize = 1;
+/// Pass the live documents of the searcher to `sink`, segment by segment
+///
+/// The documents are read in the order of the doc store, one block at a time,
+/// so an index of any size is exported with little memory.
+///
+/// # Arguments
+///
+/// * `index` - The reference to the tantivy index
+/// * `searcher` - The snapshot of the index to export
+/// * `sink` - Receives each document with all its stored fields, an error stops the export
+///
+/// # Returns
+///
+/// The number of the exported documents, or error
+pub fn export_docs(
+    index: &Index,
+    searcher: &Searcher,
+    mut sink: impl FnMut(KnownledgeDocumentWithTime) -> tantivy::Result<()>,
+) -> tantivy::Result<usize> {
+    let (title, body, create_at) = get_fields(index)?;
+    let optional_fields = get_optional_fields(index);
+    let projection = Projection::default();
+    let mut exported = 0;
+    for segment_reader in searcher.segment_readers() {
+        let store_reader = segment_reader.get_store_reader(EXPORT_CACHE_BLOCKS)?;
+        for retrieved_doc in store_reader.iter(segment_reader.alive_bitset()) {
+            sink(KnownledgeDocumentWithTime::build_from_document(
+                retrieved_doc?,
+                &title,
+                &body,
+                &create_at,
+                &optional_fields,
+                &projection,
+            )?)?;
+            exported += 1;
+        }
+    }
+    Ok(exported)
+}
+/// Write the live documents as NDJSON, one `KnownledgeDocumentWithTime` per line, see `export_docs`
+pub fn export_ndjson(
+    index: &Index,
+    searcher: &Searcher,
+    out: &mut impl std::io::Write,
+) -> tantivy::Result<usize> {
+    export_docs(index, searcher, |doc| {
+        serde_json::to_writer(&mut *out, &doc).map_err(std::io::Error::from)?;
+        out.write_all(b"\n")?;
+        Ok(())
+    })
+}
+/// The fields of a document which can be mapped by a reindex
+const REINDEX_FIELDS: [&str; 9] = [
+    "title", "body", "create_at", "category", "tags", "source", "id", "parent", "offset",
+];
+/// Copy the live documents of an index into another one, whose schema and tokenizers may differ
+///
+/// Each document is read from the stored fields of the source index and written as a new document,
+/// so its text is tokenized again by the tokenizers of the target index. The create time is kept.
+///
+/// # Arguments
+///
+/// * `from_index` - The index to read, possibly created by an older schema
+/// * `searcher` - The snapshot of `from_index` to copy
+/// * `to_index` - The index to write
+/// * `writer` - The writer of `to_index`
+/// * `mapping` - The stored field of `from_index` each field of the new documents is read from,
+///   e.g. `{"body": "content"}`, a field not mapped is read from the field of the same name
+/// * `commit_every` - Number of the documents between two commits
+///
+/// # Returns
+///
+/// The summary of the copy, the documents which can't be written are rejected with their number,
+/// or error if a mapping is invalid or the changes can't be committed
+pub fn reindex_docs(
+    from_index: &Index,
+    searcher: &Searcher,
+    to_index: &Index,
+    writer: &KnowledgeWriter,
+    mapping: &HashMap<String, String>,
+    commit_every: usize,
+) -> tantivy::Result<BulkReport> {
+    debug!(?mapping, "reindex");
+    let schema = from_index.schema();
+    let mut sources: HashMap<&str, Option<Field>> = HashMap::new();
+    for target in REINDEX_FIELDS {
+        let source = match mapping.get(target) {
+            //a mapped field must exist
+            Some(source) => Some(schema.get_field(source)?),
+            None => schema.get_field(target).ok(),
+        };
+        sources.insert(target, source);
+    }
+    if let Some(target) = mapping.keys().find(|t| !REINDEX_FIELDS.contains(&t.as_str())) {
+        return Err(TantivyError::FieldNotFound(target.to_string()));
+    }
+
+    let mut report = BulkReport::default();
+    let mut uncommitted = 0;
+    let mut number = 0;
+    for segment_reader in searcher.segment_readers() {
+        let store_reader = segment_reader.get_store_reader(EXPORT_CACHE_BLOCKS)?;
+        for retrieved_doc in store_reader.iter(segment_reader.alive_bitset()) {
+            number += 1;
+            let added = map_doc(&retrieved_doc?, &sources).and_then(|(doc, create_at)| {
+                let create_at = create_at.unwrap_or_else(now);
+                write_doc(to_index, &writer.writer(), &doc, &create_at).map_err(|e| e.to_string())
+            });
+            match added {
+                Ok(_) => {
+                    report.accepted += 1;
+                    uncommitted += 1;
+                }
+                Err(reason) => report.rejected.push(RejectedDocument {
+                    index: number,
+                    reason,
+                }),
+            }
+            if uncommitted >= commit_every.max(1) {
+                commit_changes(writer, uncommitted)?;
+                report.commits += 1;
+                uncommitted = 0;
+            }
+        }
+    }
+    if uncommitted > 0 {
+        commit_changes(writer, uncommitted)?;
+        report.commits += 1;
+    }
+    Ok(report)
+}
+/// Build the new document from the stored fields, with its create time if it has one
+fn map_doc(
+    retrieved_doc: &Document,
+    sources: &HashMap<&str, Option<Field>>,
+) -> Result<(KnownledgeDocument, Option<String>), String> {
+    let values = |target: &str| -> Vec<&Value> {
+        match sources.get(target).copied().flatten() {
+            Some(field) => retrieved_doc.get_all(field).collect(),
+            None => vec![],
+        }
+    };
+    let text = |target: &str| values(target).into_iter().find_map(value_text);
+    let create_at = match values("create_at").first() {
+        Some(Value::Date(ts)) => Some(ts.into_utc().format(&Rfc3339).map_err(|e| e.to_string())?),
+        Some(value) => value_text(value),
+        None => None,
+    };
+    let doc = KnownledgeDocument {
+        title: text("title").unwrap_or_default(),
+        body: text("body").unwrap_or_default(),
+        category: text("category"),
+        tags: values("tags").into_iter().filter_map(value_text).collect(),
+        source: text("source"),
+        id: text("id"),
+        parent: text("parent"),
+        offset: values("offset").into_iter().find_map(|v| match v {
+            Value::U64(offset) => Some(*offset),
+            _ => None,
+        }),
+        embedding: None,
+    };
+    Ok((doc, create_at))
+}
+/// The stored value as text, the facets as their path
+fn value_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Str(text) => Some(text.to_string()),
+        Value::Facet(facet) => Some(facet.to_path_string()),
+        Value::U64(v) => Some(v.to_string()),
+        Value::I64(v) => Some(v.to_string()),
+        _ => None,
+    }
+}
 /// Combine multiple queries into one BoolQuery
 fn build_bool_query(
     query_parser: &QueryParser,
@@ -364,31 +2150,125 @@ fn build_bool_query(
     }
     Ok(BooleanQuery::new(all_query))
 }
+/// Add the document, the document with the same id is replaced
+fn write_doc(
+    index: &Index,
+    index_writer: &IndexWriter,
+    doc: &KnownledgeDocument,
+    now: &str,
+) -> tantivy::Result<()> {
+    let document = make_doc(index, doc, now)?;
+    if let (Some(field), Some(id)) = (get_optional_fields(index).id, &doc.id) {
+        index_writer.delete_term(Term::from_field_text(field, id));
+    }
+    index_writer.add_document(document)?;
+    Ok(())
+}
+/// Build the tantivy document field by field, the text is taken as it is
+/// so quotes, backslashes and newlines in title or body are kept.
 fn make_doc(index: &Index, doc: &KnownledgeDocument, now: &str) -> tantivy::Result<Document> {
-    let content = format!(
-        "{{
-        \"create_at\": \"{}\",
-        \"title\": \"{}\",
-        \"body\": \"{}\"
-    }}",
-        now, doc.title, doc.body,
-    );
-    let schema = index.schema();
-    let document = schema.parse_document(content.as_str())?;
+    let (title, body, create_at) = get_fields(index)?;
+    let create_at_value = OffsetDateTime::parse(now, &Rfc3339)
+        .map_err(|e| TantivyError::InvalidArgument(e.to_string()))?;
+
+    let mut document = Document::new();
+    document.add_date(create_at, DateTime::from_utc(create_at_value));
+    document.add_text(title, &doc.title);
+    document.add_text(body, &doc.body);
+    let optional_fields = get_optional_fields(index);
+    if let (Some(field), Some(path)) = (optional_fields.category, &doc.category) {
+        document.add_facet(field, parse_doc_facet(path)?);
+    }
+    if let Some(field) = optional_fields.tags {
+        for tag in &doc.tags {
+            document.add_facet(field, parse_doc_facet(tag)?);
+        }
+    }
+    if let (Some(field), Some(source)) = (optional_fields.source, &doc.source) {
+        document.add_text(field, source);
+    }
+    if let Some(id) = &doc.id {
+        let field = optional_fields
+            .id
+            .ok_or_else(|| TantivyError::FieldNotFound("id".to_string()))?;
+        document.add_text(field, id);
+    }
+    if let (Some(field), Some(parent)) = (optional_fields.parent, &doc.parent) {
+        document.add_text(field, parent);
+    }
+    if let (Some(field), Some(offset)) = (optional_fields.offset, doc.offset) {
+        document.add_u64(field, offset);
+    }
+    let fingerprint = Fingerprint::of(&doc.title, &doc.body);
+    if let Some(field) = optional_fields.content_hash {
+        document.add_u64(field, fingerprint.hash);
+    }
+    if let Some(field) = optional_fields.simhash {
+        document.add_u64(field, fingerprint.simhash);
+    }
     Ok(document)
 }
+/// The tokenizer of the title and body fields, chosen when the index is created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextTokenizer {
+    /// cang-jie, for Chinese text
+    #[default]
+    CangJie,
+    /// tantivy's default, split on whitespace and punctuation, lowercased
+    Default,
+    /// tantivy's default with the English words stemmed
+    EnStem,
+    /// split on whitespace only
+    Whitespace,
+}
+impl TextTokenizer {
+    /// The name the tokenizer is registered by, cang-jie is registered when the index is created or loaded
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextTokenizer::CangJie => CANG_JIE,
+            TextTokenizer::Default => "default",
+            TextTokenizer::EnStem => "en_stem",
+            TextTokenizer::Whitespace => "whitespace",
+        }
+    }
+
+    /// The tokenizer of the body of the index, `None` if it's none of these
+    pub fn of(index: &Index) -> Option<Self> {
+        let schema = index.schema();
+        let body = schema.get_field("body").ok()?;
+        let FieldType::Str(options) = schema.get_field_entry(body).field_type() else {
+            return None;
+        };
+        let name = options.get_indexing_options()?.tokenizer();
+        [
+            TextTokenizer::CangJie,
+            TextTokenizer::Default,
+            TextTokenizer::EnStem,
+            TextTokenizer::Whitespace,
+        ]
+        .into_iter()
+        .find(|tokenizer| tokenizer.name() == name)
+    }
+}
 /// Create schema
 ///  
 /// #Fields
 ///
-/// * `title`: string
-/// * `body`: string
+/// * `title`: string, tokenized by `tokenizer`
+/// * `body`: string, tokenized by `tokenizer`
 /// * `created_at`: date
-fn make_schema() -> Schema {
+/// * `category`: facet
+/// * `tags`: facet
+/// * `source`: string, stored only
+/// * `id`: string, not tokenized
+/// * `parent`: string, not tokenized
+/// * `offset`: u64, stored only
+fn make_schema(tokenizer: TextTokenizer) -> Schema {
     let mut schema_builder = Schema::builder();
 
     let text_indexing = TextFieldIndexing::default()
-        .set_tokenizer(CANG_JIE) // Set custom tokenizer
+        .set_tokenizer(tokenizer.name()) // Set custom tokenizer
         .set_index_option(IndexRecordOption::WithFreqsAndPositions);
     let text_options = TextOptions::default()
         .set_indexing_options(text_indexing)
@@ -400,6 +2280,15 @@ fn make_schema() -> Schema {
     let _ = schema_builder.add_date_field("create_at", date_options);
     let _ = schema_builder.add_text_field("title", text_options.clone());
     let _ = schema_builder.add_text_field("body", text_options);
+    let facet_options = FacetOptions::default().set_stored();
+    let _ = schema_builder.add_facet_field("category", facet_options.clone());
+    let _ = schema_builder.add_facet_field("tags", facet_options);
+    let _ = schema_builder.add_text_field("source", STORED);
+    let _ = schema_builder.add_text_field("id", STRING | STORED);
+    let _ = schema_builder.add_text_field("parent", STRING | STORED);
+    let _ = schema_builder.add_u64_field("offset", STORED);
+    let _ = schema_builder.add_u64_field("content_hash", INDEXED | FAST);
+    let _ = schema_builder.add_u64_field("simhash", FAST);
 
     schema_builder.build()
 }
@@ -411,11 +2300,49 @@ fn now() -> String {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::writer::WriterConf;
     use std::io::BufRead;
     use std::io::BufReader;
 
+    /// Removes the index in the path and its vectors once dropped, even if the test fails
+    pub(crate) struct RemoveOnDrop(pub &'static str);
+
+    impl RemoveOnDrop {
+        fn remove(&self) {
+            let _ = fs::remove_dir_all(self.0);
+            let _ = fs::remove_file(crate::vector::vectors_path(self.0));
+        }
+    }
+
+    impl Drop for RemoveOnDrop {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    /// Create an empty repository in `path` for a test, with the default tokenizer and writer settings
+    ///
+    /// Keep the guard until the end of the test, it's declared first so it's dropped after the writer.
+    pub(crate) fn test_repository(path: &'static str) -> (RemoveOnDrop, Index, IndexReader, KnowledgeWriter) {
+        test_repository_with(path, TextTokenizer::default(), WriterConf::default())
+    }
+
+    /// Create an empty repository in `path` for a test, like `test_repository`
+    pub(crate) fn test_repository_with(
+        path: &'static str,
+        tokenizer: TextTokenizer,
+        conf: WriterConf,
+    ) -> (RemoveOnDrop, Index, IndexReader, KnowledgeWriter) {
+        let guard = RemoveOnDrop(path);
+        //left by a test run killed before the cleanup
+        guard.remove();
+        let (index, reader) = create_index_with(path, tokenizer).unwrap();
+        let writer = KnowledgeWriter::open(&index, reader.clone(), conf).unwrap();
+        (guard, index, reader, writer)
+    }
+
     #[test]
     fn test_now() {
         let now = now();
@@ -427,6 +2354,424 @@ mod tests {
         assert!(now.contains('.'));
     }
     #[test]
+    fn test_create_refuses_existing() {
+        let (_test, index, _reader, writer) = test_repository("index_test_create");
+        let doc = KnownledgeDocument::new("儿童感冒".to_string(), "多喝水".to_string(), None, vec![]);
+        add_doc(&index, &writer, doc).unwrap();
+        writer.commit().unwrap();
+        drop(writer);
+
+        assert!(create_index("index_test_create").is_err());
+        let (_, reader) = load_index("index_test_create").unwrap();
+        assert_eq!(1, reader.searcher().num_docs());
+    }
+    #[test]
+    fn test_special_characters_and_report() {
+        let (_test, index, reader, writer) = test_repository("index_test_report");
+        let docs = vec![
+            KnownledgeDocument {
+                title: "引号\"与反斜杠\\".to_string(),
+                body: "第一行\n第二行\t{\"json\": true}".to_string(),
+                ..Default::default()
+            },
+            KnownledgeDocument {
+                title: "坏的分类".to_string(),
+                body: "分类路径不合法".to_string(),
+                category: Some("/".to_string()),
+                ..Default::default()
+            },
+        ];
+        let report = add_doc_in_batch(&index, &writer, docs).unwrap();
+        assert_eq!(1, report.accepted.len());
+        assert_eq!(0, report.accepted[0].index);
+        assert_eq!(1, report.rejected.len());
+        assert_eq!(1, report.rejected[0].index);
+
+        let res = query_title(&index, &reader, "引号", 1).unwrap();
+        assert_eq!("引号\"与反斜杠\\", res[0].doc.title);
+        assert_eq!("第一行\n第二行\t{\"json\": true}", res[0].doc.body);
+    }
+    #[test]
+    fn test_doc_stream() {
+        let (_test, index, reader, writer) = test_repository("index_test_stream");
+        let lines = vec![
+            r#"{"title": "第一篇", "body": "内容"}"#,
+            r#"{"title": "第二篇", "body": "#,
+            r#"{"title": "第三篇", "body": "内容"}"#,
+        ];
+        let docs = lines.into_iter().enumerate().map(|(i, line)| {
+            (
+                i + 1,
+                serde_json::from_str::<KnownledgeDocument>(line).map_err(|e| e.to_string()),
+            )
+        });
+        let report = add_doc_stream(&index, &writer, docs, 1).unwrap();
+        assert_eq!(2, report.accepted);
+        assert_eq!(2, report.commits);
+        assert_eq!(2, report.rejected[0].index);
+        assert_eq!(2, reader.searcher().num_docs());
+    }
+    #[test]
+    fn test_export() {
+        let (_test, index, reader, writer) = test_repository("index_test_export");
+        let doc = KnownledgeDocument::new(
+            "儿童感冒".to_string(),
+            "多喝水".to_string(),
+            Some("/health/children".to_string()),
+            vec!["感冒".to_string()],
+        )
+        .with_id("a".to_string());
+        add_doc(&index, &writer, doc).unwrap();
+        let deleted = KnownledgeDocument::new("删除".to_string(), "".to_string(), None, vec![])
+            .with_id("b".to_string());
+        add_doc(&index, &writer, deleted).unwrap();
+        delete_by_ids(&index, &writer, &["b".to_string()]).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(1, export_ndjson(&index, &reader.searcher(), &mut out).unwrap());
+        let lines: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+        assert_eq!(1, lines.len());
+        let exported: KnownledgeDocumentWithTime = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!("儿童感冒", exported.doc.title);
+        assert_eq!(Some("/health/children".to_string()), exported.doc.category);
+        assert_eq!(Some("a".to_string()), exported.doc.id);
+        assert!(!exported.create_at.is_empty());
+        //the export can be ingested as is
+        assert!(serde_json::from_str::<KnownledgeDocument>(lines[0]).is_ok());
+    }
+    #[test]
+    fn test_reindex() {
+        let (_test, index, reader, writer) = test_repository("index_test_reindex_from");
+        let doc = KnownledgeDocument::new("儿童感冒".to_string(), "多喝水".to_string(), None, vec!["感冒".to_string()])
+            .with_id("a".to_string());
+        add_doc(&index, &writer, doc).unwrap();
+        let (_to_test, to_index, to_reader, to_writer) =
+            test_repository_with("index_test_reindex_to", TextTokenizer::Whitespace, WriterConf::default());
+
+        let invalid = HashMap::from([("body".to_string(), "content".to_string())]);
+        assert!(reindex_docs(&index, &reader.searcher(), &to_index, &to_writer, &invalid, 10).is_err());
+
+        let mapping = HashMap::from([("body".to_string(), "title".to_string())]);
+        let report = reindex_docs(&index, &reader.searcher(), &to_index, &to_writer, &mapping, 10).unwrap();
+        assert_eq!((1, 1), (report.accepted, report.commits));
+        let mut from = Vec::new();
+        export_ndjson(&index, &reader.searcher(), &mut from).unwrap();
+        let from: KnownledgeDocumentWithTime = serde_json::from_slice(&from).unwrap();
+        let mut to = Vec::new();
+        export_ndjson(&to_index, &to_reader.searcher(), &mut to).unwrap();
+        let to: KnownledgeDocumentWithTime = serde_json::from_slice(&to).unwrap();
+        assert_eq!("儿童感冒", to.doc.body);
+        assert_eq!(from.doc.tags, to.doc.tags);
+        assert_eq!(from.doc.id, to.doc.id);
+        assert_eq!(from.create_at, to.create_at);
+        //the old body is not indexed anymore
+        let old_body = |index: &Index, reader: &IndexReader| {
+            search_title_body_scored(index, &reader.searcher(), vec!["喝水"], Combiner::OR, 10).unwrap().len()
+        };
+        assert_eq!((1, 0), (old_body(&index, &reader), old_body(&to_index, &to_reader)));
+        //the text is tokenized again by the tokenizer of the new index
+        assert_eq!(Some(TextTokenizer::Whitespace), TextTokenizer::of(&to_index));
+        let found = |key: &str| {
+            search_title_body_scored(&to_index, &to_reader.searcher(), vec![key], Combiner::OR, 10).unwrap().len()
+        };
+        assert_eq!((1, 0), (found("儿童感冒"), found("感冒")));
+    }
+    #[test]
+    fn test_passages() {
+        let (_test, index, reader, writer) = test_repository("index_test_passage");
+        let docs = vec![
+            KnownledgeDocument::new(
+                "儿童感冒".to_string(),
+                "儿童感冒怎么办？多喝水。多休息！不要乱用药。".to_string(),
+                None,
+                vec![],
+            )
+            .with_id("a".to_string()),
+            KnownledgeDocument::new("老人".to_string(), "老人感冒多休息。".to_string(), None, vec![]),
+        ];
+        let options = ChunkOptions {
+            max_chars: 12,
+            overlap: 0,
+        };
+        let report = add_passages_in_batch(&index, &writer, docs, &options).unwrap();
+        assert_eq!(1, report.accepted.len());
+        assert_eq!(1, report.rejected[0].index); //no id
+        assert_eq!(2, reader.searcher().num_docs());
+
+        //passages are replaced
+        let docs = vec![KnownledgeDocument::new(
+            "儿童感冒".to_string(),
+            "多喝水。多休息！".to_string(),
+            None,
+            vec![],
+        )
+        .with_id("a".to_string())];
+        add_passages_in_batch(&index, &writer, docs, &options).unwrap();
+        assert_eq!(1, reader.searcher().num_docs());
+
+        let options = SearchOptions {
+            group_by_parent: true,
+            ..Default::default()
+        };
+        let output =
+            search_title_body(&index, &reader.searcher(), vec!["休息"], Combiner::OR, 10, &options).unwrap();
+        assert!(output.docs.is_empty());
+        assert_eq!(1, output.groups.len());
+        assert_eq!(Some("a".to_string()), output.groups[0].parent);
+        assert_eq!(Some(0), output.groups[0].passages[0].doc.doc.offset);
+    }
+    #[test]
+    fn test_retrieve_passages() {
+        let (_test, index, reader, writer) = test_repository("index_test_retrieve");
+        let docs = vec![
+            KnownledgeDocument::new(
+                "儿童感冒".to_string(),
+                "儿童感冒要多休息。儿童感冒要多喝水。".to_string(),
+                None,
+                vec![],
+            )
+            .with_id("a".to_string()),
+            KnownledgeDocument::new(
+                "老人感冒".to_string(),
+                "老人感冒要多休息。".to_string(),
+                None,
+                vec![],
+            )
+            .with_id("b".to_string()),
+        ];
+        let options = ChunkOptions {
+            max_chars: 9,
+            overlap: 0,
+        };
+        add_passages_in_batch(&index, &writer, docs, &options).unwrap();
+        assert_eq!(3, reader.searcher().num_docs());
+
+        let budget = Budget::default();
+        let passages =
+            retrieve_passages(&index, &reader.searcher(), "感冒怎么办？", 10, budget).unwrap();
+        //one passage per source document
+        assert_eq!(2, passages.len());
+        assert!(passages[0].score >= passages[1].score);
+
+        let budget = Budget {
+            max_chars: Some(12),
+            max_tokens: None,
+        };
+        let passages =
+            retrieve_passages(&index, &reader.searcher(), "儿童感冒怎么办？", 10, budget).unwrap();
+        assert_eq!(2, passages.len());
+        assert_eq!(Some("a".to_string()), passages[0].source);
+        assert_eq!(9, passages[0].len);
+        assert!(!passages[0].truncated);
+        assert_eq!(3, passages[1].len);
+        assert!(passages[1].truncated);
+    }
+    #[test]
+    fn test_hybrid_search() {
+        let path = "index_test_hybrid";
+        let (_test, index, reader, writer) = test_repository(path);
+        let mut vectors = VectorIndex::create(&crate::vector::vectors_path(path)).unwrap();
+        let doc = |title: &str, id: &str| {
+            KnownledgeDocument::new(title.to_string(), "多休息".to_string(), None, vec![])
+                .with_id(id.to_string())
+        };
+        let docs = vec![
+            doc("儿童感冒", "a").with_embedding(vec![1.0, 0.0]),
+            doc("老人感冒", "b").with_embedding(vec![0.0, 1.0]),
+            doc("儿童发烧", "c").with_embedding(vec![0.9, 0.1]),
+            KnownledgeDocument::new("无id".to_string(), "".to_string(), None, vec![])
+                .with_embedding(vec![1.0, 0.0]),
+            doc("儿童咳嗽", "d").with_embedding(vec![1.0, 0.0, 0.0]),
+        ];
+        let report = add_doc_with_vectors(&index, &writer, &mut vectors, docs, None).unwrap();
+        assert_eq!(3, report.accepted.len());
+        assert_eq!(vec![3, 4], report.rejected.iter().map(|r| r.index).collect::<Vec<_>>());
+        assert_eq!(3, vectors.len());
+
+        let searcher = reader.searcher();
+        let vector = [0.0, 1.0];
+        let options = |num| HybridOptions { num, rrf_k: RRF_K };
+        let hits =
+            search_hybrid(&index, &searcher, &vectors, vec![], Combiner::OR, Some(&vector), options(1)).unwrap();
+        assert_eq!(Some("b".to_string()), hits[0].doc.doc.id);
+        assert_eq!(None, hits[0].text_rank);
+
+        //"a" is in both rankings
+        let vector = [1.0, 0.0];
+        let keys = vec!["感冒"];
+        let hits =
+            search_hybrid(&index, &searcher, &vectors, keys, Combiner::OR, Some(&vector), options(3)).unwrap();
+        assert_eq!(3, hits.len());
+        assert_eq!(Some("a".to_string()), hits[0].doc.doc.id);
+        assert_eq!(Some(1), hits[0].vector_rank);
+        assert!(hits[0].text_rank.is_some());
+        assert!(hits[0].doc.doc.embedding.is_none());
+
+        let scored = search_title_body_scored(&index, &searcher, vec!["儿童"], Combiner::OR, 10).unwrap();
+        assert_eq!(2, scored.len());
+        assert!(scored[0].1 >= scored[1].1 && scored[1].1 > 0.0);
+
+        //the vectors are reloaded with the repository
+        let vectors = VectorIndex::open(&crate::vector::vectors_path(path)).unwrap();
+        assert_eq!(3, vectors.len());
+    }
+    #[test]
+    fn test_dedup() {
+        let path = "index_test_dedup";
+        let (_test, index, reader, writer) = test_repository(path);
+        let mut vectors = VectorIndex::create(&crate::vector::vectors_path(path)).unwrap();
+        let body = "儿童感冒怎么办？多喝水，多休息，不要乱用药，注意观察体温变化，高烧不退要及时就医。";
+        let doc = |title: &str, body: &str| {
+            KnownledgeDocument::new(title.to_string(), body.to_string(), None, vec![])
+        };
+        let docs = vec![
+            doc("儿童感冒", body).with_id("a".to_string()),
+            doc("老人骨折", "老人骨折以后要尽快去医院，拍片确认骨折的位置和程度。"),
+        ];
+        add_doc_with_vectors(&index, &writer, &mut vectors, docs, None).unwrap();
+
+        let reject = DedupOptions {
+            mode: DedupMode::Reject,
+            max_distance: 6,
+        };
+        let near = body.replace("体温变化", "体温的变化");
+        let docs = vec![
+            doc("儿童感冒", body),
+            doc("儿童感冒", &near),
+            doc("儿童发烧", "发烧超过三天要去医院。"),
+            doc("儿童发烧", "发烧超过三天要去医院。"),
+        ];
+        let report = add_doc_with_vectors(&index, &writer, &mut vectors, docs, Some(&reject)).unwrap();
+        assert_eq!(vec![2], report.accepted.iter().map(|a| a.index).collect::<Vec<_>>());
+        assert_eq!("duplicate of a", report.rejected[0].reason);
+        assert_eq!(3, reader.searcher().num_docs());
+
+        //the merged document replaces the duplicate and keeps its id
+        let merge = DedupOptions {
+            mode: DedupMode::Merge,
+            max_distance: 6,
+        };
+        let docs = vec![KnownledgeDocument::new(
+            "儿童感冒".to_string(),
+            near.clone(),
+            None,
+            vec!["儿童".to_string()],
+        )];
+        let report = add_doc_with_vectors(&index, &writer, &mut vectors, docs, Some(&merge)).unwrap();
+        assert_eq!(Some("a".to_string()), report.accepted[0].duplicate_of);
+        assert_eq!(3, reader.searcher().num_docs());
+        let found = query_title(&index, &reader, "感冒", 10).unwrap();
+        assert_eq!(Some("a".to_string()), found[0].doc.id);
+        assert_eq!(near, found[0].doc.body);
+        //a rejected document doesn't delete its duplicate
+        let docs = vec![doc("儿童感冒", body).with_id("b".to_string()).with_embedding(vec![])];
+        let report = add_doc_with_vectors(&index, &writer, &mut vectors, docs, Some(&merge)).unwrap();
+        assert_eq!("empty embedding", report.rejected[0].reason);
+        writer.commit().unwrap();
+        assert_eq!(3, reader.searcher().num_docs());
+        assert_eq!(near, query_title(&index, &reader, "感冒", 10).unwrap()[0].doc.body);
+
+        let flag = DedupOptions {
+            mode: DedupMode::Flag,
+            max_distance: 0,
+        };
+        let docs = vec![doc("儿童发烧", "发烧超过三天要去医院。")];
+        let report = add_doc_with_vectors(&index, &writer, &mut vectors, docs, Some(&flag)).unwrap();
+        assert_eq!(Some("儿童发烧".to_string()), report.accepted[0].duplicate_of);
+        assert_eq!(4, reader.searcher().num_docs());
+
+        //only the duplicate found is merged, not every document with the same content
+        let docs = vec![doc("儿童发烧", "发烧超过三天要去医院。").with_id("c".to_string())];
+        let report = add_doc_with_vectors(&index, &writer, &mut vectors, docs, Some(&merge)).unwrap();
+        assert_eq!(Some("儿童发烧".to_string()), report.accepted[0].duplicate_of);
+        assert_eq!(4, reader.searcher().num_docs());
+
+        let clusters = duplicate_clusters(&index, &reader.searcher(), 6).unwrap();
+        assert_eq!(1, clusters.len());
+        assert_eq!(2, clusters[0].docs.len());
+        assert_eq!("儿童发烧", clusters[0].docs[0].doc.title);
+    }
+    #[test]
+    fn test_search_options() {
+        let (_test, index, reader, writer) = test_repository("index_test_facet");
+        let docs = vec![
+            KnownledgeDocument::new(
+                "儿童感冒".to_string(),
+                "多喝水".to_string(),
+                Some("/健康/儿童".to_string()),
+                vec!["感冒".to_string(), "发烧".to_string()],
+            ),
+            KnownledgeDocument::new(
+                "老人感冒".to_string(),
+                "多休息".to_string(),
+                Some("健康/老人".to_string()),
+                vec!["感冒".to_string()],
+            ),
+        ];
+        add_doc_in_batch(&index, &writer, docs).unwrap();
+
+        let options = SearchOptions {
+            facets: vec![
+                FacetRequest {
+                    field: "category".to_string(),
+                    path: "/".to_string(),
+                    depth: 2,
+                },
+                FacetRequest {
+                    field: "tags".to_string(),
+                    path: "/".to_string(),
+                    depth: 1,
+                },
+            ],
+            ..Default::default()
+        };
+        let output =
+            search_title_body(&index, &reader.searcher(), vec!["感冒"], Combiner::OR, 10, &options).unwrap();
+        assert_eq!(2, output.docs.len());
+
+        let category = output.facets.get("category").unwrap();
+        assert_eq!(1, category.len());
+        assert_eq!("/健康", category[0].facet);
+        assert_eq!(2, category[0].count);
+        assert_eq!(2, category[0].children.len());
+
+        let tags = output.facets.get("tags").unwrap();
+        let cold = tags.iter().find(|t| t.facet == "/感冒").unwrap();
+        assert_eq!(2, cold.count);
+
+        let options: SearchOptions = serde_json::from_str(
+            r#"{
+                "histogram": {"interval": "month"},
+                "aggregations": {"create_at_stats": {"stats": {"field": "create_at"}}}
+            }"#,
+        )
+        .unwrap();
+        let output =
+            search_title_body(&index, &reader.searcher(), vec!["感冒"], Combiner::OR, 10, &options).unwrap();
+        assert_eq!(1, output.histogram.len());
+        assert_eq!(2, output.histogram[0].doc_count);
+        let stats = &output.aggregations.unwrap()["create_at_stats"];
+        assert_eq!(2, stats["count"].as_u64().unwrap());
+
+        let options: SearchOptions = serde_json::from_str(r#"{"fields": ["title"]}"#).unwrap();
+        let output =
+            search_title_body(&index, &reader.searcher(), vec!["感冒"], Combiner::OR, 10, &options).unwrap();
+        assert!(output.docs.iter().all(|d| !d.doc.title.is_empty()
+            && d.doc.body.is_empty()
+            && d.create_at.is_empty()
+            && d.doc.tags.is_empty()));
+        //the fields not selected are omitted, an empty selected field is kept
+        let json = serde_json::to_value(&output.docs[0]).unwrap();
+        assert_eq!(vec!["title"], json.as_object().unwrap().keys().collect::<Vec<_>>());
+        let output = search_title_body(&index, &reader.searcher(), vec!["感冒"], Combiner::OR, 10, &Default::default())
+            .unwrap();
+        let mut doc = output.docs.into_iter().next().unwrap();
+        doc.doc.body = String::new();
+        let json = serde_json::to_value(&doc).unwrap();
+        assert_eq!(Some(""), json["body"].as_str());
+        assert!(!json["create_at"].as_str().unwrap().is_empty());
+    }
+    #[test]
     fn test_all() {
         create_repository();
         load_and_search();
@@ -434,7 +2779,10 @@ mod tests {
     }
     fn create_repository() {
         let begin = std::time::Instant::now();
+        let _ = fs::remove_dir_all("index_test");
         let (index, index_reader) = create_index("index_test").unwrap();
+        let writer =
+            KnowledgeWriter::open(&index, index_reader.clone(), WriterConf::default()).unwrap();
 
         let file = std::fs::File::open("data.json").unwrap();
         let mut reader = BufReader::new(file);
@@ -456,7 +2804,7 @@ mod tests {
                 line.clear();
             }
         }
-        add_doc_in_batch(&index, &index_reader, docs).unwrap();
+        add_doc_in_batch(&index, &writer, docs).unwrap();
         let search = index_reader.searcher();
         assert_eq!(9774, search.num_docs());
         let end = std::time::Instant::now();
@@ -478,6 +2826,7 @@ mod tests {
                 .unwrap()
                 .len()
         );
+        assert!(count_title_body(&index, &reader, vec!["儿童", "头痛"], Combiner::OR).unwrap() >= 10);
         let res =
             query_title_body(&index, &reader, vec!["儿童", "头痛"], Combiner::AND, 10).unwrap();
         let query = std::time::Instant::now();
@@ -496,14 +2845,16 @@ mod tests {
     fn delete_test() {
         let begin = std::time::Instant::now();
         let (index, reader) = load_index("index_test").unwrap();
+        let writer = KnowledgeWriter::open(&index, reader.clone(), WriterConf::default()).unwrap();
 
-        delele_all(&index, &reader).unwrap();
+        delele_all(&writer).unwrap();
         add_doc(
             &index,
-            &reader,
+            &writer,
             KnownledgeDocument {
                 title: "我们一起去唱歌".to_string(),
                 body: "天天向上".to_string(),
+                ..Default::default()
             },
         )
         .unwrap();
@@ -511,7 +2862,7 @@ mod tests {
         assert_eq!(r.len(), 1);
         // println!("{:?}", r.get(0));
         let ts = &*r.get(0).unwrap().create_at;
-        delete(&index, &reader, "我们一起去唱歌", ts).unwrap();
+        delete(&index, &writer, "我们一起去唱歌", ts).unwrap();
         let serach = reader.searcher();
         assert_eq!(serach.num_docs(), 0);
         let end = std::time::Instant::now();
@@ -1,60 +1,411 @@
 //! This module implments the router APIs to interact with the Repository interface
 
-use std::sync::RwLock;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
-use crate::repository::{Combiner, KnowledgeQueryResult, KnownledgeDocument};
+use crate::repository::{
+    BulkReport, Combiner, HybridOptions, KnowledgeBulkResult, KnowledgeCommitResult,
+    KnowledgeCountResult, KnowledgeDuplicatesResult, KnowledgeFederatedResult,
+    KnowledgeHybridResult, KnowledgeIngestResult, KnowledgeQueryResult, KnowledgeRetrieveResult,
+    KnowledgeSearchOutput, KnowledgeSnapshotResult, KnowledgeStatsResult, KnownledgeDocument,
+    SearchOptions, TextTokenizer, RRF_K,
+};
 
+use super::importer::{self, MarkupImportOptions, TableImportOptions};
+use super::passage::{Budget, ChunkOptions};
 use super::repository;
+use super::snapshot;
+use super::stats;
+use super::dedup::{DedupMode, DedupOptions};
+use super::federation::{self, Normalization};
+use super::state::{AppState, Collection, CollectionError, LoadedRepository, DEFAULT_COLLECTION};
+use super::tasks::{QueueDepth, TaskError, TaskQueue};
+use axum::body::{Body, Bytes};
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::header;
+use axum::response::Response;
 use axum::{http::StatusCode, response::IntoResponse, Json};
-use serde::Deserialize;
-use tantivy::{Index, IndexReader};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 
-static mut G_INDEX: RwLock<Option<Index>> = RwLock::new(None);
-static mut G_READER: RwLock<Option<IndexReader>> = RwLock::new(None);
+/// Run the blocking repository work on `queue`, off the async runtime
+///
+/// # Returns
+///
+/// The result of the work, or the status and the message of the response if the work or the queue fails
+async fn run_blocking<T, E>(
+    queue: &TaskQueue,
+    work: impl FnOnce() -> Result<T, E> + Send + 'static,
+) -> Result<T, (StatusCode, String)>
+where
+    T: Send + 'static,
+    E: ToString + Send + 'static,
+{
+    match queue.run(work).await {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(e)) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Err(TaskError::Full) => Err((StatusCode::SERVICE_UNAVAILABLE, TaskError::Full.to_string())),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Run the write into the repository of the collection on the write queue, see `Collection::write`
+///
+/// # Returns
+///
+/// The result of the write, or the status and the message of the response,
+/// 409 while the collection is being rebuilt
+async fn run_write<T, E>(
+    queue: &TaskQueue,
+    collection: Arc<Collection>,
+    write: impl FnOnce(&LoadedRepository) -> Result<T, E> + Send + 'static,
+) -> Result<T, (StatusCode, String)>
+where
+    T: Send + 'static,
+    E: ToString + Send + 'static,
+{
+    match queue.run(move || collection.write(write)).await {
+        Ok(Ok(Ok(result))) => Ok(result),
+        Ok(Ok(Err(e))) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        Ok(Err(e)) => Err((collection_status(&e), e.to_string())),
+        Err(TaskError::Full) => Err((StatusCode::SERVICE_UNAVAILABLE, TaskError::Full.to_string())),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
 
-const REPOSITPRY_PATH: &str = "repository";
+/// The collection of the request, named by the `name` path parameter,
+/// the default collection on the `/v1/knowledge` routes
+#[derive(Debug)]
+pub struct Scoped(pub Arc<Collection>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Scoped {
+    type Rejection = (StatusCode, Json<String>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let params = Option::<Path<HashMap<String, String>>>::from_request_parts(parts, state)
+            .await
+            .unwrap_or(None);
+        let name = params
+            .and_then(|Path(mut params)| params.remove("name"))
+            .unwrap_or_else(|| DEFAULT_COLLECTION.to_string());
+        match state.collection(&name) {
+            Some(collection) => Ok(Scoped(collection)),
+            None => Err((
+                StatusCode::NOT_FOUND,
+                Json(CollectionError::NotFound(name).to_string()),
+            )),
+        }
+    }
+}
+
+impl Scoped {
+    /// The loaded repository of `collection`
+    ///
+    /// # Returns
+    ///
+    /// The repository, or the status and the message of the response if the collection isn't loaded
+    pub fn repository(collection: &Collection) -> Result<Arc<LoadedRepository>, (StatusCode, String)> {
+        collection.loaded().map_err(|e| {
+            error!("{}", e);
+            (collection_status(&e), e.to_string())
+        })
+    }
+}
+
+/// Run the change of the collections on the write queue
+async fn collection_response(
+    state: &AppState,
+    name: String,
+    change: impl FnOnce(&AppState, &str) -> Result<(), CollectionError> + Send + 'static,
+) -> (StatusCode, Json<String>) {
+    let changing = state.clone();
+    match state.write_queue().run(move || change(&changing, &name)).await {
+        Ok(Ok(())) => (StatusCode::OK, Json("OK".to_string())),
+        Ok(Err(e)) => (collection_status(&e), Json(e.to_string())),
+        Err(TaskError::Full) => (StatusCode::SERVICE_UNAVAILABLE, Json(TaskError::Full.to_string())),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())),
+    }
+}
+
+fn collection_status(e: &CollectionError) -> StatusCode {
+    match e {
+        CollectionError::InUse | CollectionError::Exists(_) | CollectionError::Rebuilding(_) => {
+            StatusCode::CONFLICT
+        }
+        CollectionError::InvalidName(_) | CollectionError::Protected | CollectionError::Rebuild(_) => {
+            StatusCode::BAD_REQUEST
+        }
+        CollectionError::NotFound(_) => StatusCode::NOT_FOUND,
+        CollectionError::NotLoaded(_) | CollectionError::Failed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateParams {
+    /// replace the existing index, which is moved into the trash
+    #[serde(default)]
+    force: bool,
+}
 
 /// The router to create new index repository
 ///
-/// This function will update the globa index and reader
+/// This function will create the repository of the default collection,
+/// it fails with 409 if the repository exists unless `force=true` is given
 #[instrument]
-pub async fn create_index() -> impl IntoResponse {
-    match repository::create_index(REPOSITPRY_PATH) {
-        Ok((index, reader)) => unsafe {
-            *G_INDEX.write().unwrap() = Some(index); //shall manage the memory older index and reader?
-            *G_READER.write().unwrap() = Some(reader);
-            (StatusCode::OK, Json("OK".to_string()))
-        },
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())),
-    }
+pub async fn create_index(
+    State(state): State<AppState>,
+    Query(params): Query<CreateParams>,
+) -> impl IntoResponse {
+    collection_response(&state, DEFAULT_COLLECTION.to_string(), move |state, name| {
+        state.create_collection(name, params.force)
+    })
+    .await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreParams {
+    /// the id of the index in the trash, see `list_trash`
+    trash: String,
+}
+
+/// The router to restore the repository of the default collection from the trash
+#[instrument]
+pub async fn restore_index(
+    State(state): State<AppState>,
+    Query(params): Query<RestoreParams>,
+) -> impl IntoResponse {
+    collection_response(&state, DEFAULT_COLLECTION.to_string(), move |state, name| {
+        state.restore_collection(name, &params.trash)
+    })
+    .await
 }
 
 /// The router to load index repository
 ///
-/// This function will update the globa index and reader
+/// This function will replace the repository of the default collection
 #[instrument]
-pub async fn load_index() -> (StatusCode, Json<String>) {
-    match repository::load_index(REPOSITPRY_PATH) {
-        Ok((index, reader)) => unsafe {
-            *G_INDEX.write().unwrap() = Some(index);
-            *G_READER.write().unwrap() = Some(reader);
-            (StatusCode::OK, Json("OK".to_string()))
-        },
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())),
+pub async fn load_index(State(state): State<AppState>) -> (StatusCode, Json<String>) {
+    collection_response(
+        &state,
+        DEFAULT_COLLECTION.to_string(),
+        AppState::load_collection,
+    )
+    .await
+}
+
+/// The router to create a collection
+///
+/// # Arguments
+///
+/// * `name`: the name of the collection, letters, digits, `-` and `_`
+/// * `force`: replace the existing index of the collection, which is moved into the trash
+#[instrument]
+pub async fn create_collection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<CreateParams>,
+) -> impl IntoResponse {
+    collection_response(&state, name, move |state, name| {
+        state.create_collection(name, params.force)
+    })
+    .await
+}
+
+/// The router to restore a collection from the index `trash` in the trash
+///
+/// The current index of the collection, if any, is moved into the trash.
+#[instrument]
+pub async fn restore_collection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<RestoreParams>,
+) -> impl IntoResponse {
+    collection_response(&state, name, move |state, name| {
+        state.restore_collection(name, &params.trash)
+    })
+    .await
+}
+
+/// The router to list the indexes in the trash, the latest first
+#[instrument]
+pub async fn list_trash(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.trash())
+}
+
+/// The router to load a collection from its directory
+#[instrument]
+pub async fn load_collection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    collection_response(&state, name, AppState::load_collection).await
+}
+
+/// The router to drop a collection, its index is moved into the trash
+#[instrument]
+pub async fn drop_collection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    collection_response(&state, name, AppState::drop_collection).await
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionInfo {
+    name: String,
+    /// the directory of the physical index the collection is an alias of
+    index: String,
+    loaded: bool,
+    /// number of the committed documents, 0 if not loaded
+    num_docs: u64,
+}
+
+/// The router to list the collections
+///
+/// # Returns
+///
+/// The collections sorted by name, with their physical index, whether they are loaded and their number of documents
+#[instrument]
+pub async fn list_collections(State(state): State<AppState>) -> impl IntoResponse {
+    let collections = state
+        .collections()
+        .iter()
+        .map(|collection| {
+            let repository = collection.repository();
+            CollectionInfo {
+                name: collection.name().to_string(),
+                index: collection.path(),
+                loaded: repository.is_some(),
+                num_docs: repository.map_or(0, |r| r.searcher().num_docs()),
+            }
+        })
+        .collect::<Vec<_>>();
+    Json(collections)
+}
+
+/// The router to commit the pending changes of the repository
+///
+/// It's needed when the commit policy doesn't commit every write, see `WriterConf`
+///
+/// # Returns
+///
+/// * `Ok(commit)`: the opstamp, the number of the committed documents and the commit time
+/// * `Err(e)`: the error message
+#[instrument]
+pub async fn commit_changes(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+) -> impl IntoResponse {
+    match run_write(state.write_queue(), collection, |loaded| loaded.writer.commit()).await {
+        Ok(info) => (StatusCode::OK, Json(KnowledgeCommitResult::SUCCESS(info))),
+        Err((status, e)) => (status, Json(KnowledgeCommitResult::Failed(e))),
+    }
+}
+
+/// The load of the search, write and export queues
+#[derive(Debug, Serialize)]
+pub struct QueueStats {
+    search: QueueDepth,
+    write: QueueDepth,
+    export: QueueDepth,
+}
+
+/// The router to monitor the queues of the blocking work
+///
+/// # Returns
+///
+/// The running and waiting tasks of the search, the write and the export queue
+#[instrument]
+pub async fn queue_stats(State(state): State<AppState>) -> impl IntoResponse {
+    Json(QueueStats {
+        search: state.search_queue().depth(),
+        write: state.write_queue().depth(),
+        export: state.export_queue().depth(),
+    })
+}
+
+/// The router to show the statistics of the repository
+///
+/// # Returns
+///
+/// The documents, the segments and the on-disk size of each field, the last commit and the writer settings
+#[instrument(skip(state))]
+pub async fn index_stats(State(state): State<AppState>, Scoped(collection): Scoped) -> impl IntoResponse {
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err((status, e)) => return (status, Json(KnowledgeStatsResult::Failed(e))),
+    };
+    match run_blocking(state.search_queue(), move || stats::index_stats(&loaded)).await {
+        Ok(stats) => (StatusCode::OK, Json(KnowledgeStatsResult::SUCCESS(stats))),
+        Err((status, e)) => (status, Json(KnowledgeStatsResult::Failed(e))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SnapshotParams {
+    /// the default collection if absent
+    collection: Option<String>,
+}
+
+/// The router to snapshot the committed index of a collection while it keeps serving
+///
+/// The snapshot is written into the `.snapshots` of the collections directory,
+/// it's restored by the `restore` command.
+///
+/// # Returns
+///
+/// The directory of the snapshot, the opstamp of the commit copied, and its size
+#[instrument]
+pub async fn snapshot_collection(
+    State(state): State<AppState>,
+    Query(params): Query<SnapshotParams>,
+) -> impl IntoResponse {
+    let name = params.collection.unwrap_or_else(|| DEFAULT_COLLECTION.to_string());
+    let Some(collection) = state.collection(&name) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(KnowledgeSnapshotResult::Failed(
+                CollectionError::NotFound(name).to_string(),
+            )),
+        );
+    };
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err((status, e)) => return (status, Json(KnowledgeSnapshotResult::Failed(e))),
+    };
+    let dir = state.snapshot_dir();
+    match run_blocking(state.search_queue(), move || snapshot::snapshot(&name, &loaded, &dir)).await {
+        Ok(info) => (StatusCode::OK, Json(KnowledgeSnapshotResult::SUCCESS(info))),
+        Err((status, e)) => (status, Json(KnowledgeSnapshotResult::Failed(e))),
     }
 }
+
 #[derive(Debug, Deserialize)]
 pub struct DocQueryOnTitleAndBody {
     args: Vec<String>,
     combiner: Combiner,
     limit: usize,
+    #[serde(flatten)]
+    options: SearchOptions,
 }
 
 fn vs_to_vas(v: &Vec<String>) -> Vec<&str> {
     v.iter().map(AsRef::as_ref).collect()
 }
 
+/// Keep the `SUCCESS` response for the queries without any search option
+fn to_query_result(output: KnowledgeSearchOutput, options: &SearchOptions) -> KnowledgeQueryResult {
+    if options.is_plain() {
+        KnowledgeQueryResult::SUCCESS(output.docs)
+    } else {
+        KnowledgeQueryResult::Detailed(output)
+    }
+}
+
 /// The router to find document by title and body
 ///
 /// This function will query the index and return the result
@@ -68,53 +419,773 @@ fn vs_to_vas(v: &Vec<String>) -> Vec<&str> {
 /// * `Ok(docs)`: the search result, including the matched documents
 /// * `Err(e)`: the error message
 #[instrument]
-pub async fn find_document(Json(payload): Json<DocQueryOnTitleAndBody>) -> impl IntoResponse {
-    let (index, reader) = unsafe { (G_INDEX.read().unwrap(), G_READER.read().unwrap()) };
+pub async fn find_document(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Json(payload): Json<DocQueryOnTitleAndBody>,
+) -> impl IntoResponse {
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err((status, e)) => return (status, Json(KnowledgeQueryResult::Failed(e))),
+    };
+    match run_blocking(state.search_queue(), move || {
+        repository::search_title_body(
+            &loaded.index,
+            &loaded.searcher(),
+            vs_to_vas(&payload.args),
+            payload.combiner,
+            payload.limit,
+            &payload.options,
+        )
+        .map(|output| to_query_result(output, &payload.options))
+    })
+    .await
+    {
+        Ok(result) => (StatusCode::OK, Json(result)),
+        Err((status, e)) => (status, Json(KnowledgeQueryResult::Failed(e))),
+    }
+}
 
-    if index.is_none() || reader.is_none() {
-        error!( "index or reader is none");
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(KnowledgeQueryResult::Failed(
-                "index or reader is none".to_string(),
-            )),
+#[derive(Debug, Deserialize)]
+pub struct DocCountOnTitleAndBody {
+    args: Vec<String>,
+    combiner: Combiner,
+}
+
+/// The router to count the documents matching title and body
+///
+/// It's much cheaper than `find_document` because no document is loaded
+///
+/// # Arguments
+///
+/// * `payload`: the query condition, including the search keywords
+///
+/// # Returns
+///
+/// * `Ok(num)`: the number of the matched documents
+/// * `Err(e)`: the error message
+#[instrument]
+pub async fn count_document(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Json(payload): Json<DocCountOnTitleAndBody>,
+) -> impl IntoResponse {
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err((status, e)) => return (status, Json(KnowledgeCountResult::Failed(e))),
+    };
+    match run_blocking(state.search_queue(), move || {
+        repository::count_title_body(
+            &loaded.index,
+            loaded.reader(),
+            vs_to_vas(&payload.args),
+            payload.combiner,
         )
-    } else {
-        match repository::query_title_body(
-            &index.as_ref().unwrap(),
-            &reader.as_ref().unwrap(),
+        .map(KnowledgeCountResult::SUCCESS)
+    })
+    .await
+    {
+        Ok(result) => (StatusCode::OK, Json(result)),
+        Err((status, e)) => (status, Json(KnowledgeCountResult::Failed(e))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DocQueryHybrid {
+    #[serde(default)]
+    args: Vec<String>,
+    combiner: Combiner,
+    /// embedding of the query, by the same model as the documents
+    vector: Option<Vec<f32>>,
+    limit: usize,
+    #[serde(default = "DocQueryHybrid::default_rrf_k")]
+    rrf_k: usize,
+}
+impl DocQueryHybrid {
+    fn default_rrf_k() -> usize {
+        RRF_K
+    }
+}
+
+/// The router to query the documents by both the keywords and the embedding
+///
+/// The BM25 ranking on title and body and the vector ranking are fused by reciprocal rank fusion
+///
+/// # Arguments
+///
+/// * `payload`: the search keywords, the embedding of the query, and the constant `k` of the fusion
+///
+/// # Returns
+///
+/// * `Ok(hits)`: the matched documents with their fused score and ranks
+/// * `Err(e)`: the error message
+#[instrument(skip(payload))]
+pub async fn find_document_hybrid(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Json(payload): Json<DocQueryHybrid>,
+) -> impl IntoResponse {
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err((status, e)) => return (status, Json(KnowledgeHybridResult::Failed(e))),
+    };
+    match run_blocking(state.search_queue(), move || {
+        repository::search_hybrid(
+            &loaded.index,
+            &loaded.searcher(),
+            &loaded.vectors.read().unwrap(),
             vs_to_vas(&payload.args),
             payload.combiner,
-            payload.limit,
-        ) {
-            Ok(docs) => (StatusCode::OK, Json(KnowledgeQueryResult::SUCCESS(docs))),
-            Err(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(KnowledgeQueryResult::Failed(e.to_string())),
-            ),
+            payload.vector.as_deref(),
+            HybridOptions {
+                num: payload.limit,
+                rrf_k: payload.rrf_k,
+            },
+        )
+        .map(KnowledgeHybridResult::SUCCESS)
+    })
+    .await
+    {
+        Ok(result) => (StatusCode::OK, Json(result)),
+        Err((status, e)) => (status, Json(KnowledgeHybridResult::Failed(e))),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FederatedQuery {
+    /// the collections to search, all the loaded collections if absent
+    #[serde(default)]
+    collections: Option<Vec<String>>,
+    args: Vec<String>,
+    combiner: Combiner,
+    limit: usize,
+    #[serde(default)]
+    normalization: Normalization,
+}
+
+/// The name and the loaded repository of a collection
+type NamedRepository = (String, Arc<LoadedRepository>);
+
+/// The loaded repositories of the collections, in the requested order
+fn federated_repositories(
+    state: &AppState,
+    names: Option<&[String]>,
+) -> Result<Vec<NamedRepository>, (StatusCode, String)> {
+    let Some(names) = names else {
+        return Ok(state
+            .collections()
+            .iter()
+            .filter_map(|c| Some((c.name().to_string(), c.repository()?)))
+            .collect());
+    };
+    names
+        .iter()
+        .map(|name| {
+            let collection = state.collection(name).ok_or_else(|| {
+                (
+                    StatusCode::NOT_FOUND,
+                    CollectionError::NotFound(name.clone()).to_string(),
+                )
+            })?;
+            Ok((name.clone(), Scoped::repository(&collection)?))
+        })
+        .collect()
+}
+
+/// The router to query several collections at once on title and body
+///
+/// The scores are normalized per collection, the ranked lists are merged,
+/// and each hit is tagged with its collection.
+///
+/// # Arguments
+///
+/// * `payload`: the collections, the search keywords and the normalization, e.g.
+///   `{"collections": ["health", "law"], "args": ["感冒"], "combiner": "OR", "limit": 10, "normalization": "minmax"}`
+///
+/// # Returns
+///
+/// * `Ok(hits)`: the best documents of all the collections with their collection and scores
+/// * `Err(e)`: the error message
+#[instrument]
+pub async fn federated_search(
+    State(state): State<AppState>,
+    Json(payload): Json<FederatedQuery>,
+) -> impl IntoResponse {
+    let repositories = match federated_repositories(&state, payload.collections.as_deref()) {
+        Ok(repositories) => repositories,
+        Err((status, e)) => return (status, Json(KnowledgeFederatedResult::Failed(e))),
+    };
+    match run_blocking(state.search_queue(), move || {
+        let mut lists = Vec::with_capacity(repositories.len());
+        for (name, loaded) in repositories {
+            //a collection can't bring more than `limit` hits to the merged list
+            let hits = repository::search_title_body_scored(
+                &loaded.index,
+                &loaded.searcher(),
+                vs_to_vas(&payload.args),
+                payload.combiner,
+                payload.limit,
+            )?;
+            lists.push((name, hits));
         }
+        Ok::<_, tantivy::TantivyError>(federation::merge(
+            lists,
+            payload.normalization,
+            payload.limit,
+        ))
+    })
+    .await
+    {
+        Ok(hits) => (StatusCode::OK, Json(KnowledgeFederatedResult::SUCCESS(hits))),
+        Err((status, e)) => (status, Json(KnowledgeFederatedResult::Failed(e))),
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct DuplicatesParams {
+    #[serde(default = "DedupOptions::default_max_distance")]
+    max_distance: u32,
+}
+
+/// The router to list the groups of duplicated documents in the repository
+///
+/// # Arguments
+///
+/// * `params`: the max different bits of near-duplicates, e.g. `?max_distance=3`, 0 for exact duplicates only
+///
+/// # Returns
+///
+/// * `Ok(clusters)`: the groups of at least two documents, with their id, title and create time
+/// * `Err(e)`: the error message
 #[instrument]
-pub async fn push_documents(Json(payload): Json<Vec<KnownledgeDocument>>) -> impl IntoResponse {
-    let (index, reader) = unsafe { (G_INDEX.write().unwrap(), G_READER.write().unwrap()) };
+pub async fn list_duplicates(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Query(params): Query<DuplicatesParams>,
+) -> impl IntoResponse {
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err((status, e)) => return (status, Json(KnowledgeDuplicatesResult::Failed(e))),
+    };
+    match run_blocking(state.search_queue(), move || {
+        repository::duplicate_clusters(
+            &loaded.index,
+            &loaded.searcher(),
+            params.max_distance,
+        )
+        .map(KnowledgeDuplicatesResult::SUCCESS)
+    })
+    .await
+    {
+        Ok(result) => (StatusCode::OK, Json(result)),
+        Err((status, e)) => (status, Json(KnowledgeDuplicatesResult::Failed(e))),
+    }
+}
 
-    if index.is_none() || reader.is_none() {
-        error!( "index or reader is none");
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json("index or reader is none".to_string()),
+#[derive(Debug, Deserialize)]
+pub struct RetrieveQuery {
+    question: String,
+    #[serde(default = "RetrieveQuery::default_limit")]
+    limit: usize,
+    /// e.g. `"max_chars": 2000` or `"max_tokens": 1000`, both may be set
+    #[serde(flatten)]
+    budget: Budget,
+}
+impl RetrieveQuery {
+    fn default_limit() -> usize {
+        5
+    }
+}
+
+/// The router to retrieve the passages answering a question, as the context of a RAG prompt
+///
+/// The passages are de-duplicated by their source document and trimmed to the budget
+///
+/// # Arguments
+///
+/// * `payload`: the question, the max number of passages and the budget in chars or tokens
+///
+/// # Returns
+///
+/// * `Ok(passages)`: the passages with their source id, title, offset and score for citations
+/// * `Err(e)`: the error message
+#[instrument]
+pub async fn retrieve_passages(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Json(payload): Json<RetrieveQuery>,
+) -> impl IntoResponse {
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err((status, e)) => return (status, Json(KnowledgeRetrieveResult::Failed(e))),
+    };
+    match run_blocking(state.search_queue(), move || {
+        repository::retrieve_passages(
+            &loaded.index,
+            &loaded.searcher(),
+            &payload.question,
+            payload.limit,
+            payload.budget,
         )
-    } else {
-        match repository::add_doc_in_batch(
-            &index.as_ref().unwrap(),
-            &reader.as_ref().unwrap(),
+        .map(KnowledgeRetrieveResult::SUCCESS)
+    })
+    .await
+    {
+        Ok(result) => (StatusCode::OK, Json(result)),
+        Err((status, e)) => (status, Json(KnowledgeRetrieveResult::Failed(e))),
+    }
+}
+
+/// Query parameters of `push_documents`
+#[derive(Debug, Default, Deserialize)]
+pub struct PushParams {
+    /// split the documents into passages, e.g. `?chunk=true&max_chars=300`
+    #[serde(default)]
+    chunk: bool,
+    #[serde(default)]
+    max_chars: Option<usize>,
+    #[serde(default)]
+    overlap: Option<usize>,
+    /// check the duplicates of the documents not split, e.g. `?dedup=reject&max_distance=3`
+    #[serde(default)]
+    dedup: Option<DedupMode>,
+    #[serde(default)]
+    max_distance: Option<u32>,
+}
+impl PushParams {
+    fn dedup_options(&self) -> Option<DedupOptions> {
+        Some(DedupOptions {
+            mode: self.dedup?,
+            max_distance: self
+                .max_distance
+                .unwrap_or_else(DedupOptions::default_max_distance),
+        })
+    }
+
+    fn chunk_options(&self) -> Option<ChunkOptions> {
+        if !self.chunk {
+            return None;
+        }
+        let default = ChunkOptions::default();
+        Some(ChunkOptions {
+            max_chars: self.max_chars.unwrap_or(default.max_chars),
+            overlap: self.overlap.unwrap_or(default.overlap),
+        })
+    }
+}
+
+/// The router to add documents
+///
+/// # Arguments
+///
+/// * `params`: whether to split the documents into passages, their ids are required then
+/// * `payload`: the documents to be added, the embeddings are kept only if they are not split
+///
+/// # Returns
+///
+/// * `Ok(report)`: the accepted documents with their create time, and the rejected ones with the reason
+/// * `Err(e)`: the error message
+#[instrument]
+pub async fn push_documents(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Query(params): Query<PushParams>,
+    Json(payload): Json<Vec<KnownledgeDocument>>,
+) -> impl IntoResponse {
+    match run_write(state.write_queue(), collection, move |loaded| match params.chunk_options() {
+        Some(options) => {
+            repository::add_passages_in_batch(&loaded.index, &loaded.writer, payload, &options)
+        }
+        None => repository::add_doc_with_vectors(
+            &loaded.index,
+            &loaded.writer,
+            &mut loaded.vectors.write().unwrap(),
             payload,
-        ) {
-            Ok(_) => (StatusCode::OK, Json("OK".to_string())),
-            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())),
+            params.dedup_options().as_ref(),
+        ),
+    })
+    .await
+    {
+        Ok(report) => (StatusCode::OK, Json(KnowledgeIngestResult::SUCCESS(report))),
+        Err((status, e)) => (status, Json(KnowledgeIngestResult::Failed(e))),
+    }
+}
+
+/// Capacity of the queue between the request body and the indexing task
+const BULK_QUEUE_SIZE: usize = 1024;
+/// Max length of a NDJSON line, the line buffer won't grow beyond it
+const BULK_MAX_LINE_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct BulkParams {
+    #[serde(default = "BulkParams::default_commit_every")]
+    commit_every: usize,
+}
+impl BulkParams {
+    fn default_commit_every() -> usize {
+        1000
+    }
+}
+
+type BulkLine = (usize, Result<KnownledgeDocument, String>);
+
+/// The router to add documents from a NDJSON body, one `KnownledgeDocument` per line
+///
+/// The body is read as a stream and the documents are indexed as they arrive in a blocking task,
+/// a bounded queue in between keeps the memory bounded whatever the body size.
+/// The changes are committed every `commit_every` documents (query parameter, 1000 by default).
+///
+/// The task takes a slot of the write queue, and holds it with the write of the collection until the
+/// whole body is read: a slow upload holds back the other writes, and a rebuild of the collection
+/// waits for it. Keep `write_concurrency` above the number of concurrent uploads.
+///
+/// # Arguments
+///
+/// * `params`: the commit policy
+/// * `body`: the NDJSON body
+///
+/// # Returns
+///
+/// * `Ok(report)`: the counts of the accepted documents and commits, and the rejected lines with the reason
+/// * `Err(e)`: the error message
+#[instrument(skip(body))]
+pub async fn bulk_documents(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Query(params): Query<BulkParams>,
+    body: Body,
+) -> impl IntoResponse {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<BulkLine>(BULK_QUEUE_SIZE);
+    let commit_every = params.commit_every;
+    let worker = run_write(state.write_queue(), collection, move |loaded| {
+        repository::add_doc_stream(
+            &loaded.index,
+            &loaded.writer,
+            std::iter::from_fn(|| rx.blocking_recv()),
+            commit_every,
+        )
+    });
+    let reading = async move {
+        let read_error = read_ndjson(body, &tx).await.err();
+        drop(tx); //end the document stream
+        read_error
+    };
+
+    //the body is read while the documents are indexed
+    match tokio::join!(worker, reading) {
+        (Ok(report), read_error) => (
+            StatusCode::OK,
+            Json(KnowledgeBulkResult::SUCCESS(BulkReport {
+                error: read_error,
+                ..report
+            })),
+        ),
+        (Err((status, e)), _) => (status, Json(KnowledgeBulkResult::Failed(e))),
+    }
+}
+
+/// The router to rebuild the collection from the documents in the body, one JSON document per line
+///
+/// The documents are indexed into a new physical index while the current one keeps serving,
+/// the collection is pointed at the new index once it's committed, and the current one is moved into
+/// the trash when the requests using it are done. Nothing changes if the body can't be read.
+/// The new index keeps the tokenizer of the current one, and the writes are rejected with 409 meanwhile.
+///
+/// Like the bulk ingestion, the slot of the write queue and the collection are held until the whole body
+/// is read, so the writes are rejected for as long as the upload lasts.
+///
+/// # Arguments
+///
+/// * `commit_every`: commit the new index after this number of documents, 0 to commit only at the end
+///
+/// # Returns
+///
+/// The report of the documents, like the bulk ingestion
+#[instrument(skip(state, body))]
+pub async fn rebuild_collection(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Query(params): Query<BulkParams>,
+    body: Body,
+) -> impl IntoResponse {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<BulkLine>(BULK_QUEUE_SIZE);
+    //set before the document stream ends, so the build sees it
+    let read_error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let commit_every = params.commit_every;
+    let building = state.clone();
+    let build_error = read_error.clone();
+    let worker = state.write_queue().run(move || {
+        building.rebuild_collection(&collection, None, |new| {
+            let report = repository::add_doc_stream(
+                &new.index,
+                &new.writer,
+                std::iter::from_fn(|| rx.blocking_recv()),
+                commit_every,
+            )
+            .map_err(|e| e.to_string())?;
+            match build_error.lock().unwrap().take() {
+                Some(e) => Err(e),
+                None => Ok(report),
+            }
+        })
+    });
+    let reading = async move {
+        if let Err(e) = read_ndjson(body, &tx).await {
+            *read_error.lock().unwrap() = Some(e);
+        }
+        drop(tx); //end the document stream
+    };
+
+    match tokio::join!(worker, reading) {
+        (Ok(Ok(report)), _) => (StatusCode::OK, Json(KnowledgeBulkResult::SUCCESS(report))),
+        (Ok(Err(e)), _) => (collection_status(&e), Json(KnowledgeBulkResult::Failed(e.to_string()))),
+        (Err(TaskError::Full), _) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(KnowledgeBulkResult::Failed(TaskError::Full.to_string())),
+        ),
+        (Err(e), _) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(KnowledgeBulkResult::Failed(e.to_string())),
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReindexRequest {
+    /// the stored field each field of the documents is read from, e.g. `{"body": "content"}`
+    #[serde(default)]
+    mapping: HashMap<String, String>,
+    /// the tokenizer of the title and body of the new index, the current one if absent
+    #[serde(default)]
+    tokenizer: Option<TextTokenizer>,
+    /// commit the new index after this number of documents
+    #[serde(default = "ReindexRequest::default_commit_every")]
+    commit_every: usize,
+}
+impl ReindexRequest {
+    fn default_commit_every() -> usize {
+        10_000
+    }
+}
+
+/// The router to reindex the collection from its own stored documents
+///
+/// The documents are copied from the current index into a new one with the current schema and
+/// the tokenizer of the request, their fields read as `mapping` says, then the collection is flipped
+/// to the new index like a rebuild. The vectors are copied as they are.
+/// The pending changes are committed first, and the writes are rejected with 409 until the flip.
+///
+/// # Returns
+///
+/// The report of the copied documents, or 400 if the mapping is invalid and nothing changed
+#[instrument(skip(state))]
+pub async fn reindex_collection(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Json(request): Json<ReindexRequest>,
+) -> impl IntoResponse {
+    if let Err((status, e)) = Scoped::repository(&collection) {
+        return (status, Json(KnowledgeBulkResult::Failed(e)));
+    }
+    let reindexing = state.clone();
+    let worker = state.write_queue().run(move || {
+        reindexing.rebuild_collection(&collection, request.tokenizer, |new| {
+            //no write gets in until the flip, the searcher sees all of them once committed
+            let loaded = collection.loaded().map_err(|e| e.to_string())?;
+            if loaded.writer.pending() > 0 {
+                loaded.writer.commit().map_err(|e| e.to_string())?;
+            }
+            let searcher = loaded.searcher();
+            let report = repository::reindex_docs(
+                &loaded.index,
+                &searcher,
+                &new.index,
+                &new.writer,
+                &request.mapping,
+                request.commit_every,
+            )
+            .map_err(|e| e.to_string())?;
+            new.copy_vectors_from(&loaded).map_err(|e| e.to_string())?;
+            Ok(report)
+        })
+    });
+    match worker.await {
+        Ok(Ok(report)) => (StatusCode::OK, Json(KnowledgeBulkResult::SUCCESS(report))),
+        Ok(Err(e)) => (collection_status(&e), Json(KnowledgeBulkResult::Failed(e.to_string()))),
+        Err(TaskError::Full) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(KnowledgeBulkResult::Failed(TaskError::Full.to_string())),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(KnowledgeBulkResult::Failed(e.to_string())),
+        ),
+    }
+}
+
+/// Lines buffered between the export task and the response body
+const EXPORT_QUEUE_SIZE: usize = 256;
+
+/// The router to export the live documents of the repository, one JSON document per line
+///
+/// The documents are streamed segment by segment from the repository as it is when the request comes,
+/// in the shape of the search results, so the export can be ingested again by the bulk or rebuild router.
+/// An error during the export ends the response early.
+///
+/// The export runs on the export queue and is paced by the client: a slow client holds an export slot,
+/// never a search slot.
+#[instrument(skip(state))]
+pub async fn export_documents(State(state): State<AppState>, Scoped(collection): Scoped) -> Response {
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err(failed) => return failed.into_response(),
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(EXPORT_QUEUE_SIZE);
+    tokio::spawn(async move {
+        let searcher = loaded.searcher();
+        let lines = tx.clone();
+        //the task waits for the client, it runs on its own queue not to hold a search slot
+        let exported = state
+            .export_queue()
+            .run(move || {
+                repository::export_docs(&loaded.index, &searcher, |doc| {
+                    let mut line = serde_json::to_vec(&doc).map_err(std::io::Error::from)?;
+                    line.push(b'\n');
+                    lines
+                        .blocking_send(Ok(Bytes::from(line)))
+                        .map_err(|_| tantivy::TantivyError::SystemError("export cancelled".to_string()))
+                })
+            })
+            .await;
+        let failed = match exported {
+            Ok(Ok(_)) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(e) = failed {
+            error!("export failed: {}", e);
+            let _ = tx.send(Err(std::io::Error::other(e))).await;
+        }
+    });
+
+    let lines = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|line| (line, rx)) });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+/// Split the body into lines and send the parsed documents to the indexing task.
+///
+/// Blank lines are skipped, a line which is not a valid document is sent as an error.
+/// Returns error if the body can't be read or the indexing task has stopped.
+async fn read_ndjson(body: Body, tx: &tokio::sync::mpsc::Sender<BulkLine>) -> Result<(), String> {
+    let mut stream = body.into_data_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut line_no = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        buffer.extend_from_slice(&chunk);
+        while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=pos).collect();
+            line_no += 1;
+            send_ndjson_line(tx, line_no, &line).await?;
         }
+        if buffer.len() > BULK_MAX_LINE_BYTES {
+            return Err(format!(
+                "line {} exceeds {} bytes",
+                line_no + 1,
+                BULK_MAX_LINE_BYTES
+            ));
+        }
+    }
+    if !buffer.is_empty() {
+        send_ndjson_line(tx, line_no + 1, &buffer).await?;
+    }
+    Ok(())
+}
+
+async fn send_ndjson_line(
+    tx: &tokio::sync::mpsc::Sender<BulkLine>,
+    line_no: usize,
+    line: &[u8],
+) -> Result<(), String> {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return Ok(());
+    }
+    let doc = serde_json::from_slice::<KnownledgeDocument>(line).map_err(|e| e.to_string());
+    tx.send((line_no, doc))
+        .await
+        .map_err(|_| "indexing task stopped".to_string())
+}
+
+/// The router to import a CSV or TSV file
+///
+/// # Arguments
+///
+/// * `options`: how to read the table (query parameters), e.g. `?format=tsv&encoding=gbk&columns=title:标题,body:内容`
+/// * `body`: the raw content of the file
+///
+/// # Returns
+///
+/// * `Ok(report)`: the accepted rows with their create time, and the rejected rows with the reason
+/// * `Err(e)`: the error message
+#[instrument(skip(body))]
+pub async fn import_table(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Query(options): Query<TableImportOptions>,
+    body: Bytes,
+) -> impl IntoResponse {
+    match run_write(state.write_queue(), collection, move |loaded| {
+        importer::import_table(
+            &loaded.index,
+            &loaded.writer,
+            &body,
+            &options,
+        )
+        .map(KnowledgeIngestResult::SUCCESS)
+    })
+    .await
+    {
+        Ok(result) => (StatusCode::OK, Json(result)),
+        Err((status, e)) => (status, Json(KnowledgeIngestResult::Failed(e))),
+    }
+}
+
+/// The router to import a Markdown or HTML document
+///
+/// # Arguments
+///
+/// * `options`: how to read the document (query parameters), e.g. `?format=html&keep_source=true&category=/健康`
+/// * `body`: the raw content of the file
+///
+/// # Returns
+///
+/// * `Ok(report)`: the document with its create time if accepted, or the reason if rejected
+/// * `Err(e)`: the error message
+#[instrument(skip(body))]
+pub async fn import_markup(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Query(options): Query<MarkupImportOptions>,
+    body: Bytes,
+) -> impl IntoResponse {
+    match run_write(state.write_queue(), collection, move |loaded| {
+        importer::import_markup(
+            &loaded.index,
+            &loaded.writer,
+            &body,
+            &options,
+        )
+        .map(KnowledgeIngestResult::SUCCESS)
+    })
+    .await
+    {
+        Ok(result) => (StatusCode::OK, Json(result)),
+        Err((status, e)) => (status, Json(KnowledgeIngestResult::Failed(e))),
     }
 }
 
@@ -122,32 +1193,33 @@ pub async fn push_documents(Json(payload): Json<Vec<KnownledgeDocument>>) -> imp
 pub struct DocQueryOnTitle {
     title: String,
     limit: usize,
+    #[serde(flatten)]
+    options: SearchOptions,
 }
 #[instrument]
-pub async fn find_document_by_title(Json(payload): Json<DocQueryOnTitle>) -> impl IntoResponse {
-    let (index, reader) = unsafe { (G_INDEX.read().unwrap(), G_READER.read().unwrap()) };
-
-    if index.is_none() || reader.is_none() {
-        error!( "index or reader is none");
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(KnowledgeQueryResult::Failed(
-                "index or reader is none".to_string(),
-            )),
-        )
-    } else {
-        match repository::query_title(
-            &index.as_ref().unwrap(),
-            &reader.as_ref().unwrap(),
+pub async fn find_document_by_title(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Json(payload): Json<DocQueryOnTitle>,
+) -> impl IntoResponse {
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err((status, e)) => return (status, Json(KnowledgeQueryResult::Failed(e))),
+    };
+    match run_blocking(state.search_queue(), move || {
+        repository::search_title(
+            &loaded.index,
+            &loaded.searcher(),
             &*payload.title,
             payload.limit,
-        ) {
-            Ok(docs) => (StatusCode::OK, Json(KnowledgeQueryResult::SUCCESS(docs))),
-            Err(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(KnowledgeQueryResult::Failed(e.to_string())),
-            ),
-        }
+            &payload.options,
+        )
+        .map(|output| to_query_result(output, &payload.options))
+    })
+    .await
+    {
+        Ok(result) => (StatusCode::OK, Json(result)),
+        Err((status, e)) => (status, Json(KnowledgeQueryResult::Failed(e))),
     }
 }
 
@@ -157,24 +1229,94 @@ pub struct DocRemove {
     ts: String,
 }
 #[instrument]
-pub async fn delete_document(Json(payload): Json<DocRemove>) -> impl IntoResponse {
-    let (index, reader) = unsafe { (G_INDEX.write().unwrap(), G_READER.write().unwrap()) };
+pub async fn delete_document(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Json(payload): Json<DocRemove>,
+) -> impl IntoResponse {
+    match run_write(state.write_queue(), collection, move |loaded| {
+        repository::delete(&loaded.index, &loaded.writer, &payload.title, &payload.ts)
+    })
+    .await
+    {
+        Ok(_) => (StatusCode::OK, Json("OK".to_string())),
+        Err((status, e)) => (status, Json(e)),
+    }
+}
 
-    if index.is_none() || reader.is_none() {
-        error!( "index or reader is none");
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json("index or reader is none".to_string()),
-        )
-    } else {
-        match repository::delete(
-            &index.as_ref().unwrap(),
-            &reader.as_ref().unwrap(),
-            &*payload.title,
-            &*payload.ts
-        ) {
-            Ok(_) => (StatusCode::OK, Json("OK".to_string())),
-            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())),
+/// One query of the multi-search batch, `type` selects the query route it's equivalent to
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MultiSearchQuery {
+    TitleBody(DocQueryOnTitleAndBody),
+    Title(DocQueryOnTitle),
+}
+
+/// The router to run a batch of queries
+///
+/// All queries run against the same searcher, so they see the same documents
+/// even if the repository is updated meanwhile.
+///
+/// # Arguments
+///
+/// * `payload`: the queries, e.g. `[{"type": "title_body", "args": ["儿童"], "combiner": "OR", "limit": 10}, {"type": "title", "title": "湿气", "limit": 3}]`
+///
+/// # Returns
+///
+/// The result of each query in the same order, a failed query doesn't fail the others
+#[instrument]
+pub async fn multi_search(
+    State(state): State<AppState>,
+    Scoped(collection): Scoped,
+    Json(payload): Json<Vec<serde_json::Value>>,
+) -> impl IntoResponse {
+    let loaded = match Scoped::repository(&collection) {
+        Ok(loaded) => loaded,
+        Err((status, e)) => {
+            let failed = payload
+                .iter()
+                .map(|_| KnowledgeQueryResult::Failed(e.clone()))
+                .collect::<Vec<_>>();
+            return (status, Json(failed));
+        }
+    };
+    let num = payload.len();
+    let searched = run_blocking(state.search_queue(), move || {
+        let searcher = loaded.searcher();
+        let results = payload
+            .into_iter()
+            .map(|query| match serde_json::from_value::<MultiSearchQuery>(query) {
+                Ok(MultiSearchQuery::TitleBody(q)) => repository::search_title_body(
+                    &loaded.index,
+                    &searcher,
+                    vs_to_vas(&q.args),
+                    q.combiner,
+                    q.limit,
+                    &q.options,
+                )
+                .map(|output| to_query_result(output, &q.options)),
+                Ok(MultiSearchQuery::Title(q)) => repository::search_title(
+                    &loaded.index,
+                    &searcher,
+                    &q.title,
+                    q.limit,
+                    &q.options,
+                )
+                .map(|output| to_query_result(output, &q.options)),
+                Err(e) => Err(tantivy::TantivyError::InvalidArgument(e.to_string())),
+            })
+            .map(|result| result.unwrap_or_else(|e| KnowledgeQueryResult::Failed(e.to_string())))
+            .collect::<Vec<_>>();
+        Ok::<_, String>(results)
+    })
+    .await;
+    match searched {
+        Ok(results) => (StatusCode::OK, Json(results)),
+        Err((status, e)) => {
+            let failed = (0..num)
+                .map(|_| KnowledgeQueryResult::Failed(e.clone()))
+                .collect::<Vec<_>>();
+            (status, Json(failed))
         }
     }
 }
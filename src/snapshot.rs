@@ -0,0 +1,204 @@
+//! Copy the committed index of a collection while the server keeps serving
+//!
+//! The segments of tantivy are immutable, so the index as of a commit is the files of the segments
+//! listed in its meta.json. The commits are held back while these files are opened, and an opened file
+//! stays readable even if a merge deletes it meanwhile (the files are opened raw, the directory of
+//! tantivy would strip their footer), so the copy is consistent without stopping the writes.
+//! A snapshot is laid out like a repository, a directory with its vectors file next to it,
+//! it can be opened as is or restored into a collection.
+//!
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use tantivy::IndexMeta;
+use tracing::{debug, info};
+
+use crate::state::{LoadedRepository, TIME_SUFFIX};
+use crate::vector;
+
+/// Times to list and open the files again when a merge deleted one in between
+const OPEN_RETRIES: usize = 5;
+
+/// The result of a snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub collection: String,
+    /// The directory of the snapshot
+    pub path: String,
+    /// The opstamp of the commit copied
+    pub opstamp: u64,
+    pub segments: usize,
+    pub num_docs: u32,
+    /// Number of the files copied, meta.json and the vectors included
+    pub files: usize,
+    pub bytes: u64,
+    /// Local time of the snapshot
+    pub created_at: String,
+}
+
+/// The committed index opened at once
+struct Committed {
+    metas: IndexMeta,
+    files: Vec<(PathBuf, File)>,
+    vectors: Option<Vec<u8>>,
+}
+
+/// Copy the last commit of the repository into a new directory of `dir`
+///
+/// # Arguments
+///
+/// * `collection` - The name of the collection, the snapshot is named after it and the time
+/// * `repository` - The loaded repository, it keeps serving during the copy
+/// * `dir` - The directory of the snapshots
+///
+/// # Returns
+///
+/// The snapshot, or error if the files can't be read or written, nothing is left behind then
+pub fn snapshot(collection: &str, repository: &LoadedRepository, dir: &Path) -> tantivy::Result<SnapshotInfo> {
+    let committed = open_committed(repository)?;
+    let path = dir.join(format!("{}.{}", collection, Local::now().format(TIME_SUFFIX)));
+    let written = write_snapshot(&committed, &path);
+    let (files, bytes) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&path);
+            let _ = fs::remove_file(vector::vectors_path(&path.to_string_lossy()));
+            return Err(e);
+        }
+    };
+    let info = SnapshotInfo {
+        collection: collection.to_string(),
+        path: path.to_string_lossy().to_string(),
+        opstamp: committed.metas.opstamp,
+        segments: committed.metas.segments.len(),
+        num_docs: committed.metas.segments.iter().map(|s| s.num_docs()).sum(),
+        files,
+        bytes,
+        created_at: Local::now().format("%Y-%m-%dT%H:%M:%S%.3f").to_string(),
+    };
+    info!(?info, "snapshot created");
+    Ok(info)
+}
+
+/// Open the files of the last commit, the vectors are read at the same time
+fn open_committed(repository: &LoadedRepository) -> tantivy::Result<Committed> {
+    let mut attempt = 1;
+    loop {
+        //no commit until the files are opened
+        let _commits = repository.writer.writer();
+        let metas = repository.index.load_metas()?;
+        let opened: io::Result<Vec<(PathBuf, File)>> = metas
+            .segments
+            .iter()
+            .flat_map(|segment| {
+                //the delete file is listed even if the segment has no deletes
+                let has_deletes = segment.has_deletes();
+                segment
+                    .list_files()
+                    .into_iter()
+                    .filter(move |file| has_deletes || file.extension().is_none_or(|ext| ext != "del"))
+            })
+            .map(|file| {
+                let opened = File::open(Path::new(repository.path()).join(&file))?;
+                Ok((file, opened))
+            })
+            .collect();
+        match opened {
+            Ok(files) => {
+                let vectors_path = vector::vectors_path(repository.path());
+                //the vectors file is replaced on save, which needs the write lock
+                let _vectors = repository.vectors.read().unwrap();
+                let vectors = vectors_path.exists().then(|| fs::read(&vectors_path)).transpose()?;
+                return Ok(Committed { metas, files, vectors });
+            }
+            Err(e) if attempt < OPEN_RETRIES => {
+                debug!(attempt, "a segment was merged away, list the files again: {}", e);
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Write the files, then meta.json which makes the directory an index
+///
+/// # Returns
+///
+/// The number of the files and bytes written
+fn write_snapshot(committed: &Committed, path: &Path) -> tantivy::Result<(usize, u64)> {
+    fs::create_dir_all(path)?;
+    let mut bytes = 0;
+    for (file, opened) in &committed.files {
+        let mut opened: &File = opened;
+        bytes += io::copy(&mut opened, &mut File::create(path.join(file))?)?;
+    }
+    let meta = serde_json::to_vec_pretty(&committed.metas).map_err(|e| tantivy::TantivyError::SystemError(e.to_string()))?;
+    fs::write(path.join("meta.json"), &meta)?;
+    bytes += meta.len() as u64;
+    let mut files = committed.files.len() + 1;
+    if let Some(vectors) = &committed.vectors {
+        fs::write(vector::vectors_path(&path.to_string_lossy()), vectors)?;
+        bytes += vectors.len() as u64;
+        files += 1;
+    }
+    Ok((files, bytes))
+}
+
+/// Copy the snapshot to `to`, to be restored from there
+pub fn copy_snapshot(snapshot: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(snapshot)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    let vectors = vector::vectors_path(&snapshot.to_string_lossy());
+    if vectors.exists() {
+        fs::copy(vectors, vector::vectors_path(&to.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{self, KnownledgeDocument};
+    use crate::state::{AppState, DEFAULT_COLLECTION};
+    use crate::tasks::QueueConf;
+    use crate::writer::WriterConf;
+
+    #[test]
+    fn test_snapshot() {
+        let root = "index_test_snapshot";
+        let state = AppState::new("index_test_snapshot_default", root, WriterConf::default(), QueueConf::default());
+        state.create_collection("health", false).unwrap();
+        let health = state.collection("health").unwrap().repository().unwrap();
+        let doc = |title: &str| KnownledgeDocument::new(title.to_string(), "多喝水".to_string(), None, vec![]);
+        repository::add_doc_in_batch(&health.index, &health.writer, vec![doc("a"), doc("b")]).unwrap();
+
+        let info = snapshot("health", &health, &state.snapshot_dir()).unwrap();
+        assert_eq!(2, info.num_docs);
+        //the changes after the snapshot are not in it
+        repository::add_doc(&health.index, &health.writer, doc("c")).unwrap();
+        let (_, reader) = repository::load_index(&info.path).unwrap();
+        assert_eq!(2, reader.searcher().num_docs());
+        drop(reader);
+        drop(health);
+
+        //restored into another collection, the default one is untouched
+        state.restore_snapshot("law", Path::new(&info.path)).unwrap();
+        let law = state.collection("law").unwrap().repository().unwrap();
+        assert_eq!(2, law.searcher().num_docs());
+        assert!(state.collection(DEFAULT_COLLECTION).unwrap().repository().is_none());
+        assert!(Path::new(&info.path).exists());
+
+        drop(law);
+        drop(state);
+        let _ = fs::remove_dir_all(root);
+    }
+}
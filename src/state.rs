@@ -0,0 +1,974 @@
+//! The state shared by the routers
+//!
+//! The server keeps its collections in an `AppState`, injected into the routers by axum's `State` extractor,
+//! so several servers can live in one process, each with its own collections.
+//! A collection is a repository in its own directory with its own schema: the `default` collection is
+//! the repository served by the `/v1/knowledge` routes, the named ones live in the collections directory.
+//! A request takes a snapshot of the loaded repository, and keeps using it even if the collection
+//! is created or loaded again meanwhile.
+//!
+//! The name of a collection is an alias of the physical index it serves. A collection is rebuilt
+//! into a new physical index while the current one keeps serving, then the alias is flipped to
+//! the new index and the old one is moved into the trash once the last request using it is done.
+//! The writes are rejected while a collection is rebuilt, they would be lost at the flip.
+//! The aliases are kept in `aliases.json` of the collections directory.
+//!
+//! An index is never deleted by a request: creating a collection over an existing index must be
+//! forced, and the replaced or dropped index is moved into the `.trash` of the collections directory,
+//! from where it can be restored. The snapshots are kept in `.snapshots` next to it, see `snapshot`.
+//!
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, TryLockError};
+
+use chrono::{Local, NaiveDateTime};
+use serde::Serialize;
+use tantivy::{Index, IndexReader, Searcher};
+use tracing::{debug, error, info, warn};
+
+use crate::repository::{self, TextTokenizer};
+use crate::snapshot;
+use crate::tasks::{QueueConf, TaskError, TaskQueue};
+use crate::vector::{self, VectorIndex};
+use crate::writer::{KnowledgeWriter, WriterConf};
+
+/// The collection served by the `/v1/knowledge` routes
+pub const DEFAULT_COLLECTION: &str = "default";
+/// Max length of a collection name
+const MAX_NAME_LEN: usize = 64;
+/// The file of the aliases in the collections directory
+const ALIASES_FILE: &str = "aliases.json";
+/// The directory of the replaced and dropped indexes in the collections directory
+const TRASH_DIR: &str = ".trash";
+/// The directory of the snapshots in the collections directory
+const SNAPSHOTS_DIR: &str = ".snapshots";
+/// The suffix of the rebuilt, trashed and snapshot indexes
+pub(crate) const TIME_SUFFIX: &str = "%Y%m%d%H%M%S%3f";
+
+/// The index, reader, writer and vector index of a loaded repository
+pub struct LoadedRepository {
+    pub index: Index,
+    pub writer: KnowledgeWriter,
+    pub vectors: RwLock<VectorIndex>,
+    /// the last field, the directory is moved into the trash after the index is closed
+    retired: Retired,
+}
+
+impl LoadedRepository {
+    /// Open the repository at `path`, it's created with the tokenizer `create` if given,
+    /// which fails if the directory is not empty
+    fn open(path: &str, create: Option<TextTokenizer>, writer_conf: WriterConf) -> tantivy::Result<Self> {
+        let vectors_path = vector::vectors_path(path);
+        let ((index, reader), vectors) = match create {
+            Some(tokenizer) => {
+                let created = repository::create_index_with(path, tokenizer)?;
+                (created, VectorIndex::create(&vectors_path)?)
+            }
+            None => {
+                let vectors = VectorIndex::open(&vectors_path)?;
+                (repository::load_index(path)?, vectors)
+            }
+        };
+        let writer = KnowledgeWriter::open(&index, reader, writer_conf)?;
+        Ok(Self {
+            index,
+            writer,
+            vectors: RwLock::new(vectors),
+            retired: Retired {
+                path: path.to_string(),
+                trash: Mutex::new(None),
+            },
+        })
+    }
+
+    /// The directory of the physical index
+    pub fn path(&self) -> &str {
+        &self.retired.path
+    }
+
+    /// The reader of the index, reloaded after each commit
+    pub fn reader(&self) -> &IndexReader {
+        self.writer.reader()
+    }
+
+    pub fn searcher(&self) -> Searcher {
+        self.writer.reader().searcher()
+    }
+
+    /// Replace the vectors by a copy of the vectors of `other`, e.g. the repository being reindexed
+    pub fn copy_vectors_from(&self, other: &LoadedRepository) -> io::Result<()> {
+        //the vectors file is replaced on save, which needs the write lock
+        let _saving = other.vectors.read().unwrap();
+        let from = vector::vectors_path(other.path());
+        if !from.exists() {
+            return Ok(());
+        }
+        let to = vector::vectors_path(self.path());
+        let mut vectors = self.vectors.write().unwrap();
+        fs::copy(&from, &to)?;
+        *vectors = VectorIndex::open(&to)?;
+        Ok(())
+    }
+}
+
+/// Move the physical index replaced by a rebuild into the trash when it's dropped
+struct Retired {
+    path: String,
+    /// the collection and the trash, set once the index is replaced
+    trash: Mutex<Option<(String, PathBuf)>>,
+}
+
+impl Drop for Retired {
+    fn drop(&mut self) {
+        if let Some((name, trash)) = self.trash.get_mut().unwrap_or_else(PoisonError::into_inner).take() {
+            if let Err(e) = move_to_trash(&name, &self.path, &trash) {
+                error!(path = %self.path, "failed to move the replaced index into the trash: {}", e);
+            }
+        }
+    }
+}
+
+fn remove_index(path: &str) {
+    let _ = fs::remove_dir_all(path); //ignore error if never created
+    let _ = fs::remove_file(vector::vectors_path(path));
+}
+
+/// The error of creating, loading or dropping a collection
+#[derive(Debug)]
+pub enum CollectionError {
+    /// the current repository is still used by a request or a task, e.g. a bulk ingestion
+    InUse,
+    /// the name is not made of letters, digits, `-` and `_`
+    InvalidName(String),
+    NotFound(String),
+    /// the directory already holds an index, creating the collection must be forced
+    Exists(String),
+    /// the default collection can't be dropped
+    Protected,
+    /// the rebuild failed, the collection still points at the current index
+    Rebuild(String),
+    /// the collection is being rebuilt, the writes are rejected meanwhile
+    Rebuilding(String),
+    /// the collection is neither created nor loaded
+    NotLoaded(String),
+    Failed(tantivy::TantivyError),
+}
+
+impl fmt::Display for CollectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectionError::InUse => write!(f, "the repository is being used"),
+            CollectionError::InvalidName(name) => write!(
+                f,
+                "invalid collection name {:?}, up to {} letters, digits, '-' and '_'",
+                name, MAX_NAME_LEN
+            ),
+            CollectionError::NotFound(name) => write!(f, "collection {} not found", name),
+            CollectionError::Exists(name) => write!(
+                f,
+                "collection {} already has an index, force to move it to the trash and create a new one",
+                name
+            ),
+            CollectionError::Protected => write!(f, "the default collection can't be dropped"),
+            CollectionError::Rebuild(e) => write!(f, "rebuild failed, nothing changed: {}", e),
+            CollectionError::Rebuilding(name) => write!(f, "collection {} is being rebuilt, retry later", name),
+            CollectionError::NotLoaded(name) => write!(f, "collection {} is not loaded", name),
+            CollectionError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CollectionError {}
+
+impl From<tantivy::TantivyError> for CollectionError {
+    fn from(e: tantivy::TantivyError) -> Self {
+        CollectionError::Failed(e)
+    }
+}
+
+impl From<io::Error> for CollectionError {
+    fn from(e: io::Error) -> Self {
+        CollectionError::Failed(e.into())
+    }
+}
+
+/// An index in the trash
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashedIndex {
+    /// the name of the index in the trash, to restore it
+    pub id: String,
+    pub collection: String,
+    /// Local time the index was moved into the trash
+    pub trashed_at: String,
+}
+
+/// A repository in its own directory, the vectors are kept next to it
+pub struct Collection {
+    name: String,
+    /// the directory of the collection, the rebuilt indexes are put next to it with a suffix
+    stem: String,
+    /// the directory of the physical index served, the stem until the first rebuild
+    path: RwLock<String>,
+    writer_conf: WriterConf,
+    repository: RwLock<Option<Arc<LoadedRepository>>>,
+    /// held by each write, and exclusively by a rebuild
+    writes: RwLock<()>,
+}
+
+impl fmt::Debug for Collection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Collection")
+            .field("name", &self.name)
+            .field("path", &self.path())
+            .finish()
+    }
+}
+
+impl Collection {
+    fn new(name: &str, stem: String, aliased: Option<String>, writer_conf: WriterConf) -> Self {
+        Self {
+            name: name.to_string(),
+            path: RwLock::new(aliased.unwrap_or_else(|| stem.clone())),
+            stem,
+            writer_conf,
+            repository: RwLock::new(None),
+            writes: RwLock::new(()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The directory of the physical index the collection points at
+    pub fn path(&self) -> String {
+        self.path.read().unwrap().clone()
+    }
+
+    /// The loaded repository, or `None` if it's neither created nor loaded
+    pub fn repository(&self) -> Option<Arc<LoadedRepository>> {
+        self.repository.read().unwrap().clone()
+    }
+
+    /// The loaded repository, or `NotLoaded` if it's neither created nor loaded
+    pub fn loaded(&self) -> Result<Arc<LoadedRepository>, CollectionError> {
+        self.repository()
+            .ok_or_else(|| CollectionError::NotLoaded(self.name.clone()))
+    }
+
+    /// Run `write` on the current repository
+    ///
+    /// The repository is taken once the write is let in, so a write waiting for a rebuild never goes
+    /// into the replaced index. The write is rejected while the collection is rebuilt.
+    pub fn write<T>(&self, write: impl FnOnce(&LoadedRepository) -> T) -> Result<T, CollectionError> {
+        let _writing = match self.writes.try_read() {
+            Ok(writing) => writing,
+            Err(TryLockError::WouldBlock) => return Err(CollectionError::Rebuilding(self.name.clone())),
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        };
+        let repository = self.loaded()?;
+        Ok(write(&repository))
+    }
+
+    /// Create a new empty repository in place of the current one
+    ///
+    /// It fails if the directory already holds an index, unless `force` is set: then the changes
+    /// of the current repository not committed are dropped, and its index is moved into `trash`.
+    pub fn create(&self, force: bool, trash: &Path) -> Result<(), CollectionError> {
+        let mut slot = self.repository.write().unwrap();
+        let path = self.path();
+        let exists = index_exists(&path);
+        if exists && !force {
+            return Err(CollectionError::Exists(self.name.clone()));
+        }
+        close(&mut slot, false)?;
+        if exists {
+            move_to_trash(&self.name, &path, trash)?;
+        }
+        self.open(&mut slot, true)
+    }
+
+    /// Load the repository from its directory, the current one is committed first
+    pub fn load(&self) -> Result<(), CollectionError> {
+        let mut slot = self.repository.write().unwrap();
+        close(&mut slot, true)?;
+        self.open(&mut slot, false)
+    }
+
+    /// Put the trashed index `trashed` back in place of the current one, which is moved into `trash`
+    ///
+    /// The collection is left unloaded if the index can't be moved.
+    pub fn restore(&self, trashed: &Path, trash: &Path) -> Result<(), CollectionError> {
+        let mut slot = self.repository.write().unwrap();
+        close(&mut slot, true)?;
+        let path = self.path();
+        if index_exists(&path) {
+            move_to_trash(&self.name, &path, trash)?;
+        } else {
+            remove_index(&path); //not an index, e.g. an empty directory
+        }
+        move_index(trashed, Path::new(&path))?;
+        info!(collection = %self.name, ?trashed, "index restored");
+        self.open(&mut slot, false)
+    }
+
+    /// Open the repository in the directory into the closed slot
+    ///
+    /// tantivy allows a single writer per index, the current writer must be closed to open the new one,
+    /// so the swap fails if the current repository is still used.
+    /// The requests wait for the swap, they never see a half-opened repository.
+    fn open(&self, slot: &mut Option<Arc<LoadedRepository>>, create: bool) -> Result<(), CollectionError> {
+        let path = self.path();
+        let opened = LoadedRepository::open(&path, create.then(TextTokenizer::default), self.writer_conf.clone())?;
+        info!(collection = %self.name, %path, "repository opened");
+        *slot = Some(Arc::new(opened));
+        Ok(())
+    }
+
+    /// Build a new physical index by `build` while the current one keeps serving,
+    /// then point the collection at it
+    ///
+    /// The new index is created with `tokenizer`, or the tokenizer of the current index if not given,
+    /// read from its directory if the collection isn't loaded; the rebuild is refused if it's unknown.
+    /// The writes in progress are waited for, then the writes are rejected until the flip,
+    /// see `write`. The new index is deleted if `build` fails, the current one is moved into `trash`
+    /// after the flip, once the requests using it are done.
+    ///
+    /// # Returns
+    ///
+    /// The result of `build` and the directory of the new index
+    fn rebuild<T>(
+        &self,
+        tokenizer: Option<TextTokenizer>,
+        trash: &Path,
+        build: impl FnOnce(&LoadedRepository) -> Result<T, String>,
+    ) -> Result<(T, String), CollectionError> {
+        let _rebuilding = self.writes.write().unwrap_or_else(PoisonError::into_inner);
+        let current = self.path();
+        let tokenizer = match tokenizer {
+            Some(tokenizer) => tokenizer,
+            None => self.current_tokenizer(&current)?,
+        };
+        let path = format!("{}.{}", self.stem, Local::now().format(TIME_SUFFIX));
+        let built = LoadedRepository::open(&path, Some(tokenizer), self.writer_conf.clone())
+            .map_err(CollectionError::Failed)
+            .and_then(|built| {
+                let result = build(&built).map_err(CollectionError::Rebuild)?;
+                built.writer.commit()?;
+                Ok((built, result))
+            });
+        let (built, result) = match built {
+            Ok(built) => built,
+            Err(e) => {
+                remove_index(&path);
+                return Err(e);
+            }
+        };
+
+        let mut slot = self.repository.write().unwrap();
+        *self.path.write().unwrap() = path.clone();
+        if let Some(replaced) = slot.replace(Arc::new(built)) {
+            *replaced.retired.trash.lock().unwrap() = Some((self.name.clone(), trash.to_path_buf()));
+        } else if index_exists(&current) {
+            if let Err(e) = move_to_trash(&self.name, &current, trash) {
+                error!(collection = %self.name, %current, "failed to move the replaced index into the trash: {}", e);
+            }
+        } else {
+            remove_index(&current); //not an index, e.g. an empty directory
+        }
+        info!(collection = %self.name, %path, "collection flipped to the rebuilt index");
+        Ok((result, path))
+    }
+
+    /// The tokenizer of the index in `current`, the default one if there is no index yet
+    fn current_tokenizer(&self, current: &str) -> Result<TextTokenizer, CollectionError> {
+        let index = match self.repository() {
+            Some(loaded) => loaded.index.clone(),
+            None if index_exists(current) => Index::open_in_dir(current)?,
+            None => return Ok(TextTokenizer::default()),
+        };
+        TextTokenizer::of(&index).ok_or_else(|| {
+            CollectionError::Rebuild("the tokenizer of the current index is unknown, give one".to_string())
+        })
+    }
+}
+
+/// Close the repository in the locked slot, no one can clone it meanwhile
+fn close(slot: &mut Option<Arc<LoadedRepository>>, commit: bool) -> Result<(), CollectionError> {
+    let Some(current) = slot.take() else {
+        return Ok(());
+    };
+    match Arc::try_unwrap(current) {
+        Ok(closed) => {
+            if commit && closed.writer.pending() > 0 {
+                if let Err(e) = closed.writer.commit() {
+                    error!("failed to commit before closing the repository: {}", e);
+                }
+            }
+            Ok(())
+        }
+        Err(current) => {
+            *slot = Some(current);
+            Err(CollectionError::InUse)
+        }
+    }
+}
+
+/// Whether the directory holds a tantivy index
+fn index_exists(path: &str) -> bool {
+    Path::new(path).join("meta.json").exists()
+}
+
+/// Move the index and its vectors into the trash, named after the collection and the time
+fn move_to_trash(name: &str, path: &str, trash: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(trash)?;
+    let trashed = trash.join(format!("{}.{}", name, Local::now().format(TIME_SUFFIX)));
+    move_index(Path::new(path), &trashed)?;
+    info!(collection = name, path, ?trashed, "index moved to the trash");
+    Ok(trashed)
+}
+
+/// Move the index directory and its vectors file if any
+fn move_index(from: &Path, to: &Path) -> io::Result<()> {
+    fs::rename(from, to)?;
+    let vectors = vector::vectors_path(&from.to_string_lossy());
+    if vectors.exists() {
+        fs::rename(vectors, vector::vectors_path(&to.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+struct State {
+    /// the directory of the named collections
+    root: String,
+    writer_conf: WriterConf,
+    collections: RwLock<HashMap<String, Arc<Collection>>>,
+    search_queue: TaskQueue,
+    write_queue: TaskQueue,
+    export_queue: TaskQueue,
+}
+
+/// The state of a server, cheap to clone
+#[derive(Clone)]
+pub struct AppState {
+    inner: Arc<State>,
+}
+
+impl fmt::Debug for AppState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppState")
+            .field("root", &self.inner.root)
+            .finish()
+    }
+}
+
+impl AppState {
+    /// The state of the collections, none of them is created or loaded yet
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The directory of the default collection
+    /// * `root` - The directory of the named collections, each in its sub-directory
+    /// * `writer_conf` - The memory budget, threads and commit policy of the writers
+    /// * `queue_conf` - The limits of the search and write queues
+    pub fn new(
+        path: impl Into<String>,
+        root: impl Into<String>,
+        writer_conf: WriterConf,
+        queue_conf: QueueConf,
+    ) -> Self {
+        let root = root.into();
+        let aliased = read_aliases(&root).remove(DEFAULT_COLLECTION);
+        let default = Collection::new(DEFAULT_COLLECTION, path.into(), aliased, writer_conf.clone());
+        Self {
+            inner: Arc::new(State {
+                root,
+                writer_conf,
+                collections: RwLock::new(HashMap::from([(
+                    DEFAULT_COLLECTION.to_string(),
+                    Arc::new(default),
+                )])),
+                search_queue: TaskQueue::new(queue_conf.search_concurrency, queue_conf.max_queued),
+                write_queue: TaskQueue::new(queue_conf.write_concurrency, queue_conf.max_queued),
+                export_queue: TaskQueue::new(queue_conf.export_concurrency, queue_conf.max_queued),
+            }),
+        }
+    }
+
+    /// The queue of the searches
+    pub fn search_queue(&self) -> &TaskQueue {
+        &self.inner.search_queue
+    }
+
+    /// The queue of the writes, commits and repository swaps
+    pub fn write_queue(&self) -> &TaskQueue {
+        &self.inner.write_queue
+    }
+
+    /// The queue of the exports, they wait for the clients and would hold the searches back
+    pub fn export_queue(&self) -> &TaskQueue {
+        &self.inner.export_queue
+    }
+
+    /// Commit the pending changes of the loaded collections if due, see `KnowledgeWriter::commit_if_due`
+    ///
+    /// The commits run on the write queue one by one; a failed one is logged and retried next time.
+    pub async fn commit_due(self) {
+        for collection in self.collections() {
+            let Some(repository) = collection.repository() else {
+                continue;
+            };
+            let committed = self
+                .write_queue()
+                .run(move || repository.writer.commit_if_due())
+                .await;
+            match committed {
+                Ok(Ok(Some(info))) => debug!(collection = collection.name(), ?info, "committed by timer"),
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => error!("failed to commit {}: {}", collection.name(), e),
+                Err(TaskError::Full) => debug!("write queue is full, commit later"),
+                Err(e) => error!("commit task failed: {}", e),
+            }
+        }
+    }
+
+    pub fn collection(&self, name: &str) -> Option<Arc<Collection>> {
+        self.inner.collections.read().unwrap().get(name).cloned()
+    }
+
+    /// All collections, sorted by name
+    pub fn collections(&self) -> Vec<Arc<Collection>> {
+        let mut collections: Vec<_> = self.inner.collections.read().unwrap().values().cloned().collect();
+        collections.sort_by(|a, b| a.name.cmp(&b.name));
+        collections
+    }
+
+    /// The loaded repository of the default collection
+    pub fn repository(&self) -> Option<Arc<LoadedRepository>> {
+        self.collection(DEFAULT_COLLECTION)?.repository()
+    }
+
+    /// Create the collection, see `Collection::create`
+    pub fn create_collection(&self, name: &str, force: bool) -> Result<(), CollectionError> {
+        self.get_or_add(name)?.create(force, &self.trash_dir())
+    }
+
+    /// The indexes in the trash, the latest first
+    pub fn trash(&self) -> Vec<TrashedIndex> {
+        let Ok(entries) = fs::read_dir(self.trash_dir()) else {
+            return vec![];
+        };
+        let mut trashed: Vec<TrashedIndex> = entries
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| {
+                let id = entry.file_name().to_string_lossy().to_string();
+                let (collection, stamp) = trashed_id(&id)?;
+                let trashed_at = NaiveDateTime::parse_from_str(stamp, TIME_SUFFIX)
+                    .map_or_else(|_| stamp.to_string(), |t| t.format("%Y-%m-%dT%H:%M:%S%.3f").to_string());
+                Some(TrashedIndex {
+                    collection: collection.to_string(),
+                    trashed_at,
+                    id,
+                })
+            })
+            .collect();
+        trashed.sort_by(|a, b| b.trashed_at.cmp(&a.trashed_at));
+        trashed
+    }
+
+    /// Restore the collection from the index `id` in the trash, the current index is moved into the trash
+    pub fn restore_collection(&self, name: &str, id: &str) -> Result<(), CollectionError> {
+        let trashed = self.trash_dir().join(id);
+        if trashed_id(id).map(|(collection, _)| collection) != Some(name) || !trashed.is_dir() {
+            return Err(CollectionError::NotFound(id.to_string()));
+        }
+        self.get_or_add(name)?.restore(&trashed, &self.trash_dir())
+    }
+
+    /// The directory of the physical index of the collection, following its alias, whether it's loaded or not
+    pub fn index_path(&self, name: &str) -> Result<String, CollectionError> {
+        if let Some(collection) = self.collection(name) {
+            return Ok(collection.path());
+        }
+        match read_aliases(&self.inner.root).remove(name) {
+            Some(path) => Ok(path),
+            None => self.collection_path(name),
+        }
+    }
+
+    /// Load the collection from its directory
+    pub fn load_collection(&self, name: &str) -> Result<(), CollectionError> {
+        let collection = match self.collection(name) {
+            Some(collection) => collection,
+            None => {
+                let path = self.index_path(name)?;
+                if !Path::new(&path).is_dir() {
+                    return Err(CollectionError::NotFound(name.to_string()));
+                }
+                self.get_or_add(name)?
+            }
+        };
+        collection.load()
+    }
+
+    /// Rebuild the collection into a new index by `build`, then flip its alias, see `Collection::rebuild`
+    pub fn rebuild_collection<T>(
+        &self,
+        collection: &Collection,
+        tokenizer: Option<TextTokenizer>,
+        build: impl FnOnce(&LoadedRepository) -> Result<T, String>,
+    ) -> Result<T, CollectionError> {
+        let (result, _) = collection.rebuild(tokenizer, &self.trash_dir(), build)?;
+        self.save_aliases();
+        Ok(result)
+    }
+
+    /// Restore the collection from a snapshot, the current index is moved into the trash
+    ///
+    /// The snapshot is copied, it can be restored again.
+    pub fn restore_snapshot(&self, name: &str, snapshot: &Path) -> Result<(), CollectionError> {
+        if !index_exists(&snapshot.to_string_lossy()) {
+            return Err(CollectionError::NotFound(snapshot.to_string_lossy().to_string()));
+        }
+        let collection = self.get_or_add(name)?;
+        //fails if another process writes the index, before anything is moved
+        if collection.repository().is_none() && index_exists(&collection.path()) {
+            collection.load()?;
+        }
+        let copied = format!("{}.{}", collection.stem, Local::now().format(TIME_SUFFIX));
+        let restored = snapshot::copy_snapshot(snapshot, Path::new(&copied))
+            .map_err(CollectionError::from)
+            .and_then(|_| collection.restore(Path::new(&copied), &self.trash_dir()));
+        if restored.is_err() {
+            remove_index(&copied);
+        }
+        restored
+    }
+
+    /// Close the collection and move its index into the trash
+    pub fn drop_collection(&self, name: &str) -> Result<(), CollectionError> {
+        if name == DEFAULT_COLLECTION {
+            return Err(CollectionError::Protected);
+        }
+        let mut collections = self.inner.collections.write().unwrap();
+        let collection = collections
+            .get(name)
+            .ok_or_else(|| CollectionError::NotFound(name.to_string()))?;
+        close(&mut collection.repository.write().unwrap(), false)?;
+        let path = collection.path();
+        if Path::new(&path).exists() {
+            move_to_trash(name, &path, &self.trash_dir())?;
+        }
+        collections.remove(name);
+        info!(collection = name, "collection dropped");
+        drop(collections);
+        self.save_aliases();
+        Ok(())
+    }
+
+    /// Load the default collection if it exists and every collection in the collections directory
+    ///
+    /// A collection which fails to load is logged and left unloaded, the others are still served.
+    ///
+    /// # Returns
+    ///
+    /// The collections failed to load, with the error
+    pub fn load_all(&self) -> Vec<(String, CollectionError)> {
+        let mut failed = Vec::new();
+        let default = self.collection(DEFAULT_COLLECTION).unwrap();
+        if Path::new(&default.path()).is_dir() {
+            if let Err(e) = default.load() {
+                error!(collection = DEFAULT_COLLECTION, "failed to load: {}", e);
+                failed.push((DEFAULT_COLLECTION.to_string(), e));
+            }
+        }
+        let Ok(entries) = fs::read_dir(&self.inner.root) else {
+            return failed;
+        };
+        //the rebuilt indexes have a suffix, they are found by their alias
+        let mut names: Vec<String> = entries
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| valid_name(name))
+            .chain(read_aliases(&self.inner.root).into_keys())
+            .filter(|name| name != DEFAULT_COLLECTION)
+            .collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            if let Err(e) = self.load_collection(&name) {
+                error!(collection = name, "failed to load: {}", e);
+                failed.push((name, e));
+            }
+        }
+        failed
+    }
+
+    /// The directory of the snapshots taken by the server
+    pub fn snapshot_dir(&self) -> PathBuf {
+        Path::new(&self.inner.root).join(SNAPSHOTS_DIR)
+    }
+
+    fn trash_dir(&self) -> PathBuf {
+        Path::new(&self.inner.root).join(TRASH_DIR)
+    }
+
+    /// Save the physical index of each collection, errors are logged
+    fn save_aliases(&self) {
+        let aliases: HashMap<String, String> = self
+            .collections()
+            .iter()
+            .map(|c| (c.name.clone(), c.path()))
+            .collect();
+        let path = Path::new(&self.inner.root).join(ALIASES_FILE);
+        let tmp = path.with_extension("json.tmp");
+        let saved = fs::create_dir_all(&self.inner.root)
+            .and_then(|_| fs::write(&tmp, serde_json::to_vec_pretty(&aliases)?))
+            .and_then(|_| fs::rename(&tmp, &path));
+        if let Err(e) = saved {
+            error!("failed to save the aliases: {}", e);
+        }
+    }
+
+    fn collection_path(&self, name: &str) -> Result<String, CollectionError> {
+        if !valid_name(name) {
+            return Err(CollectionError::InvalidName(name.to_string()));
+        }
+        Ok(Path::new(&self.inner.root).join(name).to_string_lossy().to_string())
+    }
+
+    fn get_or_add(&self, name: &str) -> Result<Arc<Collection>, CollectionError> {
+        if let Some(collection) = self.collection(name) {
+            return Ok(collection);
+        }
+        let stem = self.collection_path(name)?;
+        fs::create_dir_all(&self.inner.root).map_err(tantivy::TantivyError::from)?;
+        let aliased = read_aliases(&self.inner.root).remove(name);
+        let mut collections = self.inner.collections.write().unwrap();
+        let collection = collections.entry(name.to_string()).or_insert_with(|| {
+            Arc::new(Collection::new(name, stem, aliased, self.inner.writer_conf.clone()))
+        });
+        Ok(collection.clone())
+    }
+}
+
+/// The physical index of each collection, the stale entries are ignored
+fn read_aliases(root: &str) -> HashMap<String, String> {
+    let path = Path::new(root).join(ALIASES_FILE);
+    let Ok(content) = fs::read(&path) else {
+        return HashMap::new();
+    };
+    match serde_json::from_slice::<HashMap<String, String>>(&content) {
+        Ok(aliases) => aliases
+            .into_iter()
+            .filter(|(_, path)| Path::new(path).is_dir())
+            .collect(),
+        Err(e) => {
+            warn!("ignore the broken {:?}: {}", path, e);
+            HashMap::new()
+        }
+    }
+}
+
+/// The collection and the time suffix of an index in the trash
+fn trashed_id(id: &str) -> Option<(&str, &str)> {
+    let (collection, stamp) = id.split_once('.')?;
+    (valid_name(collection) && !stamp.is_empty() && stamp.chars().all(|c| c.is_ascii_digit()))
+        .then_some((collection, stamp))
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::KnownledgeDocument;
+
+    #[test]
+    fn test_swap() {
+        let state = AppState::new(
+            "index_test_state",
+            "index_test_state_collections",
+            WriterConf::default(),
+            QueueConf::default(),
+        );
+        assert!(state.repository().is_none());
+        state.create_collection(DEFAULT_COLLECTION, false).unwrap();
+
+        let doc = KnownledgeDocument::new("儿童感冒".to_string(), "多喝水".to_string(), None, vec![]);
+        let loaded = state.repository().unwrap();
+        repository::add_doc(&loaded.index, &loaded.writer, doc).unwrap();
+        //a request still holds the repository
+        assert!(matches!(
+            state.load_collection(DEFAULT_COLLECTION),
+            Err(CollectionError::InUse)
+        ));
+        assert_eq!(1, loaded.searcher().num_docs());
+        drop(loaded);
+
+        state.load_collection(DEFAULT_COLLECTION).unwrap();
+        assert_eq!(1, state.repository().unwrap().searcher().num_docs());
+        //the index is kept unless forced, then it's moved into the trash
+        assert!(matches!(
+            state.create_collection(DEFAULT_COLLECTION, false),
+            Err(CollectionError::Exists(_))
+        ));
+        assert_eq!(1, state.repository().unwrap().searcher().num_docs());
+        state.create_collection(DEFAULT_COLLECTION, true).unwrap();
+        assert_eq!(0, state.repository().unwrap().searcher().num_docs());
+
+        let trash = state.trash();
+        assert_eq!(1, trash.len());
+        assert_eq!(DEFAULT_COLLECTION, trash[0].collection);
+        assert!(matches!(
+            state.restore_collection("law", &trash[0].id),
+            Err(CollectionError::NotFound(_))
+        ));
+        state.restore_collection(DEFAULT_COLLECTION, &trash[0].id).unwrap();
+        assert_eq!(1, state.repository().unwrap().searcher().num_docs());
+        //the empty index replaced by the restore is in the trash
+        assert_eq!(1, state.trash().len());
+
+        drop(state);
+        let _ = fs::remove_dir_all("index_test_state");
+        let _ = fs::remove_file(vector::vectors_path("index_test_state"));
+        let _ = fs::remove_dir_all("index_test_state_collections");
+    }
+
+    #[test]
+    fn test_collections() {
+        let root = "index_test_collections";
+        let state = AppState::new(
+            "index_test_collections_default",
+            root,
+            WriterConf::default(),
+            QueueConf::default(),
+        );
+        assert!(matches!(
+            state.create_collection("../etc", false),
+            Err(CollectionError::InvalidName(_))
+        ));
+        assert!(matches!(
+            state.load_collection("health"),
+            Err(CollectionError::NotFound(_))
+        ));
+        state.create_collection("health", false).unwrap();
+        state.create_collection("law", false).unwrap();
+
+        let health = state.collection("health").unwrap().repository().unwrap();
+        let doc = KnownledgeDocument::new("儿童感冒".to_string(), "多喝水".to_string(), None, vec![]);
+        repository::add_doc(&health.index, &health.writer, doc).unwrap();
+        drop(health);
+        let law = state.collection("law").unwrap().repository().unwrap();
+        assert_eq!(0, law.searcher().num_docs());
+        drop(law);
+
+        //a new server finds the collections in the directory
+        drop(state);
+        let state = AppState::new(
+            "index_test_collections_default",
+            root,
+            WriterConf::default(),
+            QueueConf::default(),
+        );
+        //a broken collection doesn't keep the others from loading
+        fs::create_dir(Path::new(root).join("broken")).unwrap();
+        let failed = state.load_all();
+        assert_eq!(vec!["broken"], failed.iter().map(|(name, _)| name).collect::<Vec<_>>());
+        let names: Vec<String> = state.collections().iter().map(|c| c.name().to_string()).collect();
+        assert_eq!(vec!["broken", "default", "health", "law"], names);
+        assert!(state.collection("broken").unwrap().repository().is_none());
+        assert!(state.repository().is_none());
+        let health = state.collection("health").unwrap();
+        assert_eq!(1, health.repository().unwrap().searcher().num_docs());
+
+        assert!(matches!(
+            state.drop_collection(DEFAULT_COLLECTION),
+            Err(CollectionError::Protected)
+        ));
+        state.drop_collection("health").unwrap();
+        assert!(state.collection("health").is_none());
+        assert!(!Path::new(&health.path()).exists());
+        //a dropped collection comes back from the trash
+        let trashed = state.trash().remove(0);
+        assert_eq!("health", trashed.collection);
+        state.restore_collection("health", &trashed.id).unwrap();
+        assert_eq!(1, state.collection("health").unwrap().repository().unwrap().searcher().num_docs());
+
+        drop(state);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let root = "index_test_rebuild";
+        let state = AppState::new("index_test_rebuild_default", root, WriterConf::default(), QueueConf::default());
+        state.create_collection("health", false).unwrap();
+        let health = state.collection("health").unwrap();
+        let old = health.repository().unwrap();
+        let doc = |title: &str| KnownledgeDocument::new(title.to_string(), "多喝水".to_string(), None, vec![]);
+        repository::add_doc(&old.index, &old.writer, doc("儿童感冒")).unwrap();
+
+        //a failed rebuild leaves the collection as it was
+        let failed = state.rebuild_collection(&health, None, |_| Err::<(), _>("broken input".to_string()));
+        assert!(matches!(failed, Err(CollectionError::Rebuild(_))));
+        assert_eq!(old.path(), health.path());
+
+        let built = state.rebuild_collection(&health, Some(TextTokenizer::EnStem), |new| {
+            //the writes would be lost at the flip
+            assert!(matches!(health.write(|_| ()), Err(CollectionError::Rebuilding(_))));
+            repository::add_doc_in_batch(&new.index, &new.writer, vec![doc("a"), doc("b")]).map_err(|e| e.to_string())
+        });
+        assert!(built.is_ok());
+        assert!(health.write(|_| ()).is_ok());
+        let new_path = health.path();
+        assert_ne!(old.path(), new_path);
+        assert_eq!(2, health.repository().unwrap().searcher().num_docs());
+        //the requests holding the old index still search it until they are done
+        assert_eq!(1, old.searcher().num_docs());
+        let old_path = old.path().to_string();
+        drop(old);
+        assert!(!Path::new(&old_path).exists());
+        assert_eq!(vec!["health"], state.trash().iter().map(|t| t.collection.as_str()).collect::<Vec<_>>());
+
+        //the alias survives the restart
+        drop(health);
+        drop(state);
+        let state = AppState::new("index_test_rebuild_default", root, WriterConf::default(), QueueConf::default());
+        assert_eq!(new_path, state.index_path("health").unwrap());
+        assert!(state.load_all().is_empty());
+        let health = state.collection("health").unwrap();
+        assert_eq!(new_path, health.path());
+        assert_eq!(2, health.repository().unwrap().searcher().num_docs());
+        //a rebuild keeps the tokenizer unless another one is given
+        state.rebuild_collection(&health, None, |_| Ok(())).unwrap();
+        assert_eq!(Some(TextTokenizer::EnStem), TextTokenizer::of(&health.repository().unwrap().index));
+
+        //the tokenizer of a collection not loaded is read from its index
+        drop(health);
+        drop(state);
+        let state = AppState::new("index_test_rebuild_default", root, WriterConf::default(), QueueConf::default());
+        let health = state.get_or_add("health").unwrap();
+        let unloaded = health.path();
+        state.rebuild_collection(&health, None, |_| Ok(())).unwrap();
+        assert_eq!(Some(TextTokenizer::EnStem), TextTokenizer::of(&health.repository().unwrap().index));
+        assert!(!Path::new(&unloaded).exists());
+        assert_eq!(3, state.trash().len());
+
+        drop(health);
+        drop(state);
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,171 @@
+//! The statistics of a loaded repository, read from its live index and searcher
+//!
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tantivy::schema::Schema;
+use tantivy::space_usage::PerFieldSpaceUsage;
+
+use crate::state::LoadedRepository;
+use crate::writer::WriterConf;
+
+/// The statistics of a repository
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexStats {
+    /// Number of the live documents, passages included
+    pub num_docs: u64,
+    /// Number of the deleted documents not merged away yet
+    pub num_deleted_docs: u64,
+    pub segments: Vec<SegmentStats>,
+    /// On-disk bytes of the searched segments
+    pub total_bytes: u64,
+    /// On-disk bytes of each indexed field, the doc store is not split by field
+    pub fields: BTreeMap<String, FieldStats>,
+    /// On-disk bytes of the doc store of all segments
+    pub store_bytes: u64,
+    /// Opstamp of the last commit
+    pub opstamp: u64,
+    /// Local time of the last commit, by this server or else when meta.json was written
+    pub last_commit_at: Option<String>,
+    pub writer: WriterStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentStats {
+    pub id: String,
+    pub num_docs: u32,
+    pub num_deleted_docs: u32,
+    pub bytes: u64,
+}
+
+/// On-disk bytes of a field by part of the index
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FieldStats {
+    pub termdict: u64,
+    pub postings: u64,
+    pub positions: u64,
+    pub fast_fields: u64,
+    pub fieldnorms: u64,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriterStats {
+    #[serde(flatten)]
+    pub conf: WriterConf,
+    /// Number of the changed documents not committed yet
+    pub pending: usize,
+}
+
+/// Collect the statistics of the repository as it's searched now
+///
+/// # Returns
+///
+/// The statistics, or error if the index files can't be read
+pub fn index_stats(repository: &LoadedRepository) -> tantivy::Result<IndexStats> {
+    let searcher = repository.searcher();
+    let schema = repository.index.schema();
+    let usage = searcher.space_usage()?;
+
+    let mut fields: BTreeMap<String, FieldStats> = BTreeMap::new();
+    let mut store_bytes = 0;
+    for segment in usage.segments() {
+        add_field_bytes(&mut fields, &schema, segment.termdict(), |f, b| f.termdict += b);
+        add_field_bytes(&mut fields, &schema, segment.postings(), |f, b| f.postings += b);
+        add_field_bytes(&mut fields, &schema, segment.positions(), |f, b| f.positions += b);
+        add_field_bytes(&mut fields, &schema, segment.fast_fields(), |f, b| f.fast_fields += b);
+        add_field_bytes(&mut fields, &schema, segment.fieldnorms(), |f, b| f.fieldnorms += b);
+        store_bytes += segment.store().total().get_bytes();
+    }
+    let segments = searcher
+        .segment_readers()
+        .iter()
+        .zip(usage.segments())
+        .map(|(reader, segment)| SegmentStats {
+            id: reader.segment_id().uuid_string(),
+            num_docs: reader.num_docs(),
+            num_deleted_docs: reader.num_deleted_docs(),
+            bytes: segment.total().get_bytes(),
+        })
+        .collect::<Vec<_>>();
+
+    let writer = &repository.writer;
+    let last_commit_at = match writer.last_commit() {
+        Some(info) => Some(info.committed_at),
+        None => meta_modified_at(repository.path()),
+    };
+    Ok(IndexStats {
+        num_docs: searcher.num_docs(),
+        num_deleted_docs: segments.iter().map(|s| s.num_deleted_docs as u64).sum(),
+        total_bytes: usage.total().get_bytes(),
+        segments,
+        fields,
+        store_bytes,
+        opstamp: repository.index.load_metas()?.opstamp,
+        last_commit_at,
+        writer: WriterStats {
+            conf: writer.conf().clone(),
+            pending: writer.pending(),
+        },
+    })
+}
+
+/// Add the bytes of each field in `usage` by `add`
+fn add_field_bytes(
+    fields: &mut BTreeMap<String, FieldStats>,
+    schema: &Schema,
+    usage: &PerFieldSpaceUsage,
+    add: impl Fn(&mut FieldStats, u64),
+) {
+    for (field, field_usage) in usage.fields() {
+        let bytes = field_usage.total().get_bytes();
+        let stats = fields.entry(schema.get_field_name(*field).to_string()).or_default();
+        add(stats, bytes);
+        stats.total += bytes;
+    }
+}
+
+/// When meta.json was written, i.e. the last commit of any writer
+fn meta_modified_at(path: &str) -> Option<String> {
+    let modified = fs::metadata(Path::new(path).join("meta.json")).ok()?.modified().ok()?;
+    Some(DateTime::<Local>::from(modified).format("%Y-%m-%dT%H:%M:%S%.3f").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{self, KnownledgeDocument};
+    use crate::state::AppState;
+    use crate::tasks::QueueConf;
+
+    #[test]
+    fn test_stats() {
+        let root = "index_test_stats";
+        let state = AppState::new("index_test_stats_default", root, WriterConf::default(), QueueConf::default());
+        state.create_collection("health", false).unwrap();
+        let health = state.collection("health").unwrap().repository().unwrap();
+        let doc = |title: &str, id: &str| {
+            KnownledgeDocument::new(title.to_string(), "多喝水".to_string(), None, vec![]).with_id(id.to_string())
+        };
+        repository::add_doc_in_batch(&health.index, &health.writer, vec![doc("儿童感冒", "a"), doc("发烧", "b")]).unwrap();
+        repository::delete_by_ids(&health.index, &health.writer, &["b".to_string()]).unwrap();
+
+        let stats = index_stats(&health).unwrap();
+        assert_eq!((1, 1), (stats.num_docs, stats.num_deleted_docs));
+        assert_eq!(1, stats.segments.len());
+        assert!(stats.total_bytes > 0);
+        assert!(stats.fields["title"].postings > 0);
+        assert!(stats.store_bytes > 0);
+        assert_eq!(Some(stats.opstamp), health.writer.last_commit().map(|c| c.opstamp));
+        assert!(stats.last_commit_at.is_some());
+        assert_eq!(WriterConf::default().memory_budget, stats.writer.conf.memory_budget);
+
+        drop(health);
+        drop(state);
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,221 @@
+//! Run the blocking repository work off the async runtime
+//!
+//! Searches, writes and commits of tantivy block the thread, running them on the tokio workers
+//! stalls every other request. They run on the blocking thread pool instead, through three queues:
+//! one for the searches and one for the writes, so a large commit doesn't hold the searches back,
+//! and one for the exports, which are paced by the clients reading them.
+//! Each queue runs a bounded number of tasks at once and rejects new tasks when too many are waiting.
+//!
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+/// Struct containing configurations of the task queues
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueConf {
+    /// Searches running at once, the number of CPUs by default
+    #[serde(default = "QueueConf::default_search_concurrency")]
+    pub search_concurrency: usize,
+    /// Writes running at once, they share one writer and wait for each other on commit.
+    /// A bulk upload holds its slot until the body is read
+    #[serde(default = "QueueConf::default_write_concurrency")]
+    pub write_concurrency: usize,
+    /// Exports running at once, a slow client holds its slot until it has read the export
+    #[serde(default = "QueueConf::default_export_concurrency")]
+    pub export_concurrency: usize,
+    /// Max waiting tasks of each queue, the requests beyond are rejected
+    #[serde(default = "QueueConf::default_max_queued")]
+    pub max_queued: usize,
+}
+
+impl QueueConf {
+    fn default_search_concurrency() -> usize {
+        std::thread::available_parallelism().map_or(4, |n| n.get())
+    }
+    fn default_write_concurrency() -> usize {
+        2
+    }
+    fn default_export_concurrency() -> usize {
+        2
+    }
+    fn default_max_queued() -> usize {
+        256
+    }
+}
+
+impl Default for QueueConf {
+    fn default() -> Self {
+        Self {
+            search_concurrency: Self::default_search_concurrency(),
+            write_concurrency: Self::default_write_concurrency(),
+            export_concurrency: Self::default_export_concurrency(),
+            max_queued: Self::default_max_queued(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TaskError {
+    /// too many tasks are waiting
+    Full,
+    /// the task panicked
+    Failed(String),
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::Full => write!(f, "too many requests are waiting, retry later"),
+            TaskError::Failed(e) => write!(f, "task failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TaskError {}
+
+/// The load of a queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueDepth {
+    pub running: usize,
+    pub queued: usize,
+    pub concurrency: usize,
+    pub max_queued: usize,
+}
+
+/// A queue of blocking tasks
+pub struct TaskQueue {
+    permits: Arc<Semaphore>,
+    concurrency: usize,
+    max_queued: usize,
+    queued: Arc<AtomicUsize>,
+    running: Arc<AtomicUsize>,
+}
+
+/// Decrease the counter when the task leaves the stage, even if the request is cancelled
+struct Counted(Arc<AtomicUsize>);
+
+impl Counted {
+    fn enter(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter.clone())
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl TaskQueue {
+    pub fn new(concurrency: usize, max_queued: usize) -> Self {
+        let concurrency = concurrency.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency)),
+            concurrency,
+            max_queued,
+            queued: Arc::new(AtomicUsize::new(0)),
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Run `f` on the blocking thread pool once the queue has room
+    ///
+    /// The slot is held by the blocking task, a cancelled request frees it only once `f` returns.
+    ///
+    /// # Returns
+    ///
+    /// The result of `f`, or error if the queue is full or `f` panicked
+    pub async fn run<T, F>(&self, f: F) -> Result<T, TaskError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let queued = Counted::enter(&self.queued);
+        if self.queued.load(Ordering::SeqCst) > self.max_queued {
+            return Err(TaskError::Full);
+        }
+        //the semaphore is never closed
+        let permit = self.permits.clone().acquire_owned().await.unwrap();
+        drop(queued);
+        let running = Counted::enter(&self.running);
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let _running = running;
+            f()
+        })
+        .await
+            .map_err(|e| TaskError::Failed(e.to_string()))
+    }
+
+    pub fn depth(&self) -> QueueDepth {
+        QueueDepth {
+            running: self.running.load(Ordering::SeqCst),
+            queued: self.queued.load(Ordering::SeqCst),
+            concurrency: self.concurrency,
+            max_queued: self.max_queued,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_queue() {
+        let queue = Arc::new(TaskQueue::new(1, 1));
+        let (tx, rx) = mpsc::channel::<()>();
+
+        //blocks the only slot until released
+        let running = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.run(move || rx.recv().is_ok()).await }
+        });
+        while queue.depth().running == 0 {
+            tokio::task::yield_now().await;
+        }
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.run(|| 2).await }
+        });
+        while queue.depth().queued == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(queue.run(|| 3).await, Err(TaskError::Full)));
+        assert_eq!(1, queue.depth().queued);
+
+        tx.send(()).unwrap();
+        assert!(running.await.unwrap().unwrap());
+        assert_eq!(2, waiting.await.unwrap().unwrap());
+        let depth = queue.depth();
+        assert_eq!((0, 0), (depth.running, depth.queued));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled() {
+        let queue = Arc::new(TaskQueue::new(1, 1));
+        let (tx, rx) = mpsc::channel::<()>();
+        let cancelled = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.run(move || rx.recv().is_ok()).await }
+        });
+        while queue.depth().running == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        //the work goes on after the request is gone, so does its slot
+        cancelled.abort();
+        assert!(cancelled.await.unwrap_err().is_cancelled());
+        assert_eq!(1, queue.depth().running);
+        tx.send(()).unwrap();
+        assert_eq!(2, queue.run(|| 2).await.unwrap());
+        assert_eq!(0, queue.depth().running);
+    }
+}
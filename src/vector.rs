@@ -0,0 +1,492 @@
+//! Approximate nearest neighbour index of the document embeddings
+//!
+//! The embeddings are computed by the clients and pushed with the documents,
+//! they are kept in a HNSW graph keyed by the document id and persisted in a file
+//! next to the repository directory, e.g. `repository.vectors`.
+//! The vectors are normalized, the similarity is the cosine similarity.
+//!
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Max neighbours of a node on the upper layers, twice on the bottom layer
+const MAX_NEIGHBOURS: usize = 16;
+/// Size of the candidate list when linking a new node
+const EF_CONSTRUCTION: usize = 100;
+/// Min size of the candidate list when searching
+const EF_SEARCH: usize = 64;
+/// Header of the persisted file
+const MAGIC: &[u8; 4] = b"KVEC";
+const VERSION: u32 = 1;
+
+/// The path of the vector file of the repository at `index_path`
+pub fn vectors_path(index_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.vectors", index_path.trim_end_matches('/')))
+}
+
+struct Node {
+    id: String,
+    vector: Vec<f32>,
+    /// neighbours on each layer, from the bottom layer up to the level of the node
+    neighbours: Vec<Vec<u32>>,
+    /// replaced or removed, still used to walk the graph
+    deleted: bool,
+}
+
+/// A node and its distance to the query, ordered by the distance
+#[derive(Clone, Copy)]
+struct Candidate {
+    distance: f32,
+    node: usize,
+}
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance)
+    }
+}
+
+pub struct VectorIndex {
+    path: PathBuf,
+    /// dimension of the vectors, 0 until the first vector is added
+    dim: usize,
+    nodes: Vec<Node>,
+    /// document id -> live node
+    ids: HashMap<String, usize>,
+    entry: Option<usize>,
+    max_level: usize,
+    /// state of the xorshift generator drawing the node levels
+    seed: u64,
+}
+
+impl VectorIndex {
+    /// An empty index persisted at `path`, the existing file is removed
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(Self::empty(path))
+    }
+
+    /// Load the index persisted at `path`, or an empty one if the file doesn't exist
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::empty(path));
+        }
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u32(&mut file)? != VERSION {
+            return Err(invalid_data("not a vector file"));
+        }
+        let mut index = Self::empty(path);
+        index.dim = read_u32(&mut file)? as usize;
+        index.max_level = read_u32(&mut file)? as usize;
+        index.seed = read_u64(&mut file)?;
+        let entry = read_u32(&mut file)?;
+        let count = read_u32(&mut file)? as usize;
+        for i in 0..count {
+            let deleted = read_u32(&mut file)? != 0;
+            let len = read_u32(&mut file)? as usize;
+            let mut id = vec![0u8; len];
+            file.read_exact(&mut id)?;
+            let id = String::from_utf8(id).map_err(|e| invalid_data(&e.to_string()))?;
+            let mut vector = Vec::with_capacity(index.dim);
+            for _ in 0..index.dim {
+                vector.push(f32::from_bits(read_u32(&mut file)?));
+            }
+            let levels = read_u32(&mut file)? as usize;
+            let mut neighbours = Vec::with_capacity(levels);
+            for _ in 0..levels {
+                let len = read_u32(&mut file)? as usize;
+                let mut layer = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let n = read_u32(&mut file)?;
+                    if n as usize >= count {
+                        return Err(invalid_data("neighbour out of range"));
+                    }
+                    layer.push(n);
+                }
+                neighbours.push(layer);
+            }
+            if !deleted {
+                index.ids.insert(id.clone(), i);
+            }
+            index.nodes.push(Node {
+                id,
+                vector,
+                neighbours,
+                deleted,
+            });
+        }
+        if count > 0 {
+            if entry as usize >= count {
+                return Err(invalid_data("entry out of range"));
+            }
+            index.entry = Some(entry as usize);
+        }
+        Ok(index)
+    }
+
+    fn empty(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            dim: 0,
+            nodes: vec![],
+            ids: HashMap::new(),
+            entry: None,
+            max_level: 0,
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    /// Number of the live vectors
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Check the vector can be added or searched with
+    pub fn check(&self, vector: &[f32]) -> Result<(), String> {
+        if vector.is_empty() {
+            return Err("empty embedding".to_string());
+        }
+        if self.dim != 0 && vector.len() != self.dim {
+            return Err(format!(
+                "embedding dimension {} doesn't match {}",
+                vector.len(),
+                self.dim
+            ));
+        }
+        if vector.iter().any(|v| !v.is_finite()) {
+            return Err("embedding is not finite".to_string());
+        }
+        if vector.iter().all(|v| *v == 0.0) {
+            return Err("embedding is zero".to_string());
+        }
+        Ok(())
+    }
+
+    /// Add the vector of the document, replacing the previous one of the same id
+    ///
+    /// The vector is expected to pass `check`.
+    pub fn insert(&mut self, id: &str, vector: &[f32]) {
+        self.remove(id);
+        if self.dim == 0 {
+            self.dim = vector.len();
+        }
+        let vector = normalize(vector);
+        let level = self.random_level();
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            id: id.to_string(),
+            vector,
+            neighbours: vec![vec![]; level + 1],
+            deleted: false,
+        });
+        self.ids.insert(id.to_string(), node);
+
+        let mut entry = match self.entry {
+            Some(entry) => entry,
+            None => {
+                self.entry = Some(node);
+                self.max_level = level;
+                return;
+            }
+        };
+        let query = self.nodes[node].vector.clone();
+        for layer in (level + 1..=self.max_level).rev() {
+            entry = self.search_layer(&query, entry, 1, layer)[0].node;
+        }
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(&query, entry, EF_CONSTRUCTION, layer);
+            let selected: Vec<u32> = candidates
+                .iter()
+                .filter(|c| c.node != node)
+                .take(MAX_NEIGHBOURS)
+                .map(|c| c.node as u32)
+                .collect();
+            for n in &selected {
+                self.link(*n as usize, node, layer);
+            }
+            self.nodes[node].neighbours[layer] = selected;
+            entry = candidates[0].node;
+        }
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry = Some(node);
+        }
+    }
+
+    /// Remove the vector of the document
+    ///
+    /// # Returns
+    ///
+    /// Whether the document had a vector
+    pub fn remove(&mut self, id: &str) -> bool {
+        match self.ids.remove(id) {
+            Some(node) => {
+                self.nodes[node].deleted = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Find the documents whose vectors are the most similar to the query
+    ///
+    /// # Returns
+    ///
+    /// At most `num` (id, cosine similarity), the most similar first
+    pub fn search(&self, query: &[f32], num: usize) -> Vec<(String, f32)> {
+        let mut entry = match self.entry {
+            Some(entry) if query.len() == self.dim && num > 0 => entry,
+            _ => return vec![],
+        };
+        let query = normalize(query);
+        for layer in (1..=self.max_level).rev() {
+            entry = self.search_layer(&query, entry, 1, layer)[0].node;
+        }
+        //the removed nodes are walked but not returned
+        let ef = EF_SEARCH.max(num) + self.nodes.len() - self.ids.len();
+        self.search_layer(&query, entry, ef.min(self.nodes.len()), 0)
+            .into_iter()
+            .filter(|c| !self.nodes[c.node].deleted)
+            .take(num)
+            .map(|c| (self.nodes[c.node].id.clone(), 1.0 - c.distance))
+            .collect()
+    }
+
+    /// Write the index to its file
+    ///
+    /// The graph is rebuilt without the removed vectors if they are the majority.
+    /// The file is replaced atomically, a crash leaves the previous version.
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.nodes.len() > 2 * self.ids.len() {
+            self.compact();
+        }
+        let tmp = self.path.with_extension("vectors.tmp");
+        {
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            file.write_all(MAGIC)?;
+            file.write_all(&VERSION.to_le_bytes())?;
+            file.write_all(&(self.dim as u32).to_le_bytes())?;
+            file.write_all(&(self.max_level as u32).to_le_bytes())?;
+            file.write_all(&self.seed.to_le_bytes())?;
+            file.write_all(&(self.entry.unwrap_or(0) as u32).to_le_bytes())?;
+            file.write_all(&(self.nodes.len() as u32).to_le_bytes())?;
+            for node in &self.nodes {
+                file.write_all(&(node.deleted as u32).to_le_bytes())?;
+                file.write_all(&(node.id.len() as u32).to_le_bytes())?;
+                file.write_all(node.id.as_bytes())?;
+                for v in &node.vector {
+                    file.write_all(&v.to_bits().to_le_bytes())?;
+                }
+                file.write_all(&(node.neighbours.len() as u32).to_le_bytes())?;
+                for layer in &node.neighbours {
+                    file.write_all(&(layer.len() as u32).to_le_bytes())?;
+                    for n in layer {
+                        file.write_all(&n.to_le_bytes())?;
+                    }
+                }
+            }
+            file.into_inner()?.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Rebuild the graph from the live vectors
+    fn compact(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.ids.clear();
+        self.entry = None;
+        self.max_level = 0;
+        for node in nodes.into_iter().filter(|n| !n.deleted) {
+            self.insert(&node.id, &node.vector);
+        }
+    }
+
+    /// Add `node` to the neighbours of `to`, dropping the farthest one if there are too many
+    fn link(&mut self, to: usize, node: usize, layer: usize) {
+        let max = if layer == 0 {
+            2 * MAX_NEIGHBOURS
+        } else {
+            MAX_NEIGHBOURS
+        };
+        self.nodes[to].neighbours[layer].push(node as u32);
+        if self.nodes[to].neighbours[layer].len() > max {
+            let mut candidates: Vec<Candidate> = self.nodes[to].neighbours[layer]
+                .iter()
+                .map(|n| Candidate {
+                    distance: distance(&self.nodes[to].vector, &self.nodes[*n as usize].vector),
+                    node: *n as usize,
+                })
+                .collect();
+            candidates.sort();
+            self.nodes[to].neighbours[layer] = candidates
+                .iter()
+                .take(max)
+                .map(|c| c.node as u32)
+                .collect();
+        }
+    }
+
+    /// Beam search on a layer of the graph
+    ///
+    /// # Returns
+    ///
+    /// At most `ef` nodes, the nearest first, never empty
+    fn search_layer(&self, query: &[f32], entry: usize, ef: usize, layer: usize) -> Vec<Candidate> {
+        let first = Candidate {
+            distance: distance(query, &self.nodes[entry].vector),
+            node: entry,
+        };
+        let mut visited = HashSet::from([entry]);
+        let mut candidates = BinaryHeap::from([Reverse(first)]);
+        let mut nearest = BinaryHeap::from([first]);
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let farthest = nearest.peek().map_or(f32::MAX, |c| c.distance);
+            if candidate.distance > farthest && nearest.len() >= ef {
+                break;
+            }
+            let neighbours = match self.nodes[candidate.node].neighbours.get(layer) {
+                Some(neighbours) => neighbours,
+                None => continue,
+            };
+            for n in neighbours {
+                let n = *n as usize;
+                if !visited.insert(n) {
+                    continue;
+                }
+                let next = Candidate {
+                    distance: distance(query, &self.nodes[n].vector),
+                    node: n,
+                };
+                let farthest = nearest.peek().map_or(f32::MAX, |c| c.distance);
+                if nearest.len() < ef || next.distance < farthest {
+                    candidates.push(Reverse(next));
+                    nearest.push(next);
+                    if nearest.len() > ef {
+                        nearest.pop();
+                    }
+                }
+            }
+        }
+        nearest.into_sorted_vec()
+    }
+
+    /// Draw the level of a new node, the probability of each level up is 1 / MAX_NEIGHBOURS
+    fn random_level(&mut self) -> usize {
+        let mut level = 0;
+        loop {
+            self.seed ^= self.seed << 13;
+            self.seed ^= self.seed >> 7;
+            self.seed ^= self.seed << 17;
+            if !self.seed.is_multiple_of(MAX_NEIGHBOURS as u64) || level >= 16 {
+                return level;
+            }
+            level += 1;
+        }
+    }
+}
+
+fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    vector.iter().map(|v| v / norm).collect()
+}
+
+/// Cosine distance of the normalized vectors
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    1.0 - a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>()
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points on a circle, the neighbours of a point are the points next to it
+    fn circle(i: usize) -> Vec<f32> {
+        let angle = i as f32 * std::f32::consts::PI / 500.0;
+        vec![angle.cos(), angle.sin(), 0.5]
+    }
+
+    #[test]
+    fn test_search() {
+        let path = Path::new("vector_test_search.vectors");
+        let mut index = VectorIndex::create(path).unwrap();
+        for i in 0..1000 {
+            index.insert(&i.to_string(), &circle(i));
+        }
+        assert_eq!(1000, index.len());
+        let found = index.search(&circle(300), 3);
+        let ids: Vec<&str> = found.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!("300", ids[0]);
+        assert!(ids.contains(&"299") && ids.contains(&"301"));
+        assert!((found[0].1 - 1.0).abs() < 1e-5);
+
+        //replace and remove
+        index.insert("300", &circle(700));
+        assert!(index.remove("301"));
+        assert_eq!(999, index.len());
+        let found = index.search(&circle(300), 3);
+        assert!(found.iter().all(|(id, _)| id != "300" && id != "301"));
+        let ids: Vec<String> = index.search(&circle(700), 2).into_iter().map(|(id, _)| id).collect();
+        assert!(ids.contains(&"300".to_string()) && ids.contains(&"700".to_string()));
+
+        assert!(index.check(&[1.0, 2.0]).is_err());
+        assert!(index.check(&[0.0, 0.0, 0.0]).is_err());
+        assert!(index.check(&[1.0, f32::NAN, 0.0]).is_err());
+        assert!(index.check(&[1.0, 0.0, 0.0]).is_ok());
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let path = Path::new("vector_test_save.vectors");
+        let mut index = VectorIndex::create(path).unwrap();
+        for i in 0..100 {
+            index.insert(&i.to_string(), &circle(i * 10));
+        }
+        for i in 0..60 {
+            index.remove(&i.to_string());
+        }
+        index.save().unwrap();
+        assert_eq!(40, index.nodes.len()); //compacted
+
+        let index = VectorIndex::open(path).unwrap();
+        assert_eq!(40, index.len());
+        assert_eq!("80", index.search(&circle(800), 1)[0].0);
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,244 @@
+//! Keep the repository in sync with a directory of documents
+//!
+//! The directory is scanned periodically, the Markdown and HTML files are indexed with their path as the document id.
+//! The first scan compares the files with the documents in the repository,
+//! the next ones compare the files with the previous scan.
+//!
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use serde::Serialize;
+use tantivy::Index;
+use tracing::{error, info, warn};
+
+use crate::config_service::WatchConf;
+use crate::importer::{self, MarkupFormat, MarkupImportOptions};
+use crate::repository::{self, KnownledgeDocument};
+use crate::state::{AppState, CollectionError, DEFAULT_COLLECTION};
+use crate::writer::KnowledgeWriter;
+
+/// Changes applied by a scan
+#[derive(Debug, Default, Serialize)]
+pub struct ScanSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub failed: usize,
+}
+
+pub struct DirectoryWatcher {
+    conf: WatchConf,
+    /// path -> modified time of the files seen in the previous scan, `None` before the first scan
+    known: Option<HashMap<String, SystemTime>>,
+}
+
+impl DirectoryWatcher {
+    pub fn new(conf: WatchConf) -> Self {
+        Self { conf, known: None }
+    }
+
+    /// Scan the directory and apply the changes to the repository
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The reference to the tantivy index
+    /// * `writer` - The shared writer of the index
+    ///
+    /// # Returns
+    ///
+    /// The summary of the changes, or error if the directory can't be read or the changes can't be committed
+    pub fn scan(&mut self, index: &Index, writer: &KnowledgeWriter) -> anyhow::Result<ScanSummary> {
+        let files = list_files(Path::new(&self.conf.dir))?;
+        let known = match self.known.take() {
+            Some(known) => known,
+            None => self.indexed_files(index, writer)?,
+        };
+
+        let mut summary = ScanSummary::default();
+        let mut docs = Vec::new();
+        for (path, modified) in &files {
+            match known.get(path) {
+                Some(previous) if previous >= modified => continue,
+                Some(_) => summary.updated += 1,
+                None => summary.added += 1,
+            }
+            match self.read_file(path) {
+                Ok(doc) => docs.push(doc),
+                Err(e) => {
+                    warn!(path = %path, "failed to read file: {}", e);
+                    summary.failed += 1;
+                }
+            }
+        }
+        let removed: Vec<String> = known
+            .keys()
+            .filter(|path| !files.contains_key(*path))
+            .cloned()
+            .collect();
+        summary.deleted = removed.len();
+
+        if !docs.is_empty() {
+            let report = repository::add_doc_in_batch(index, writer, docs)?;
+            summary.failed += report.rejected.len();
+        }
+        repository::delete_by_ids(index, writer, &removed)?;
+        self.known = Some(files);
+        Ok(summary)
+    }
+
+    /// The watched files in the repository, with their time of indexing as the modified time
+    fn indexed_files(
+        &self,
+        index: &Index,
+        writer: &KnowledgeWriter,
+    ) -> anyhow::Result<HashMap<String, SystemTime>> {
+        let prefix = Path::new(&self.conf.dir);
+        let mut indexed = HashMap::new();
+        for (id, create_at) in repository::list_ids(index, writer.reader())? {
+            if !Path::new(&id).starts_with(prefix) {
+                continue;
+            }
+            //`create_at` is the local time written in RFC3339 with `Z`
+            let create_at = DateTime::parse_from_rfc3339(&create_at)?.naive_utc();
+            indexed.insert(id, local_naive_to_system_time(create_at));
+        }
+        Ok(indexed)
+    }
+
+    fn read_file(&self, path: &str) -> anyhow::Result<KnownledgeDocument> {
+        let format = MarkupFormat::from_path(Path::new(path))
+            .ok_or_else(|| anyhow::Error::msg("unsupported file type"))?;
+        let content = std::fs::read(path)?;
+        let options = MarkupImportOptions {
+            format,
+            keep_source: self.conf.keep_source,
+            title: Path::new(path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string()),
+            category: self.conf.category.clone(),
+            ..Default::default()
+        };
+        Ok(importer::read_markup(&content, &options)?.with_id(path.to_string()))
+    }
+}
+
+/// List the Markdown and HTML files in the directory recursively, with their modified time
+fn list_files(dir: &Path) -> anyhow::Result<HashMap<String, SystemTime>> {
+    let mut files = HashMap::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path: PathBuf = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if MarkupFormat::from_path(&path).is_some() {
+                let modified = std::fs::metadata(&path)?.modified()?;
+                files.insert(path.to_string_lossy().to_string(), modified);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Convert the naive local time (seconds precision) to system time
+fn local_naive_to_system_time(naive: NaiveDateTime) -> SystemTime {
+    match naive.and_local_timezone(Local).earliest() {
+        Some(local) => SystemTime::from(local),
+        None => SystemTime::UNIX_EPOCH,
+    }
+}
+
+/// Scan the directory every `interval_secs` until the process exits
+///
+/// The scan runs on the blocking thread pool against the collection of the configuration,
+/// it's skipped while the collection is not loaded or being rebuilt.
+pub async fn run(state: AppState, conf: WatchConf) {
+    info!(?conf, "watch directory");
+    let mut interval = tokio::time::interval(Duration::from_secs(conf.interval_secs.max(1)));
+    let collection = conf
+        .collection
+        .clone()
+        .unwrap_or_else(|| DEFAULT_COLLECTION.to_string());
+    let mut watcher = DirectoryWatcher::new(conf.clone());
+    loop {
+        interval.tick().await;
+        let scanned_collection = state.collection(&collection);
+        let scanned = tokio::task::spawn_blocking(move || {
+            let summary = scanned_collection.map(|scanned| {
+                scanned.write(|repository| watcher.scan(&repository.index, &repository.writer))
+            });
+            (watcher, summary)
+        })
+        .await;
+        match scanned {
+            Ok((w, summary)) => {
+                watcher = w;
+                match summary {
+                    Some(Ok(Ok(summary))) => {
+                        if summary.added + summary.updated + summary.deleted + summary.failed > 0 {
+                            info!(?summary, "directory scanned");
+                        }
+                    }
+                    Some(Ok(Err(e))) => error!("failed to scan directory: {}", e),
+                    Some(Err(e @ CollectionError::Rebuilding(_))) => info!("skip scanning: {}", e),
+                    Some(Err(_)) | None => warn!(%collection, "collection is not loaded, skip scanning"),
+                }
+            }
+            Err(e) => {
+                //the state of the files is lost with the task, a new watcher finds them in the repository
+                error!("watcher task failed, keep watching: {}", e);
+                watcher = DirectoryWatcher::new(conf.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::tests::test_repository;
+
+    #[test]
+    fn test_scan() {
+        let dir = "watch_test_docs";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(format!("{}/sub", dir)).unwrap();
+        std::fs::write(format!("{}/a.md", dir), "# 儿童感冒\n\n多喝水").unwrap();
+        std::fs::write(format!("{}/sub/b.html", dir), "<title>老人感冒</title><p>多休息</p>").unwrap();
+        std::fs::write(format!("{}/c.txt", dir), "ignored").unwrap();
+        //the create time is in seconds, let it be after the modified time
+        std::thread::sleep(Duration::from_millis(1100));
+        let (_test, index, reader, writer) = test_repository("index_test_watch");
+
+        let conf = WatchConf {
+            dir: dir.to_string(),
+            interval_secs: 1,
+            keep_source: false,
+            category: None,
+            collection: None,
+        };
+        let mut watcher = DirectoryWatcher::new(conf.clone());
+        let summary = watcher.scan(&index, &writer).unwrap();
+        assert_eq!(2, summary.added);
+        assert_eq!(2, reader.searcher().num_docs());
+
+        //a restarted watcher finds the files in the repository
+        let mut watcher = DirectoryWatcher::new(conf);
+        let summary = watcher.scan(&index, &writer).unwrap();
+        assert_eq!(0, summary.added + summary.updated);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::write(format!("{}/a.md", dir), "# 儿童发烧\n\n多喝水").unwrap();
+        std::fs::remove_file(format!("{}/sub/b.html", dir)).unwrap();
+        let summary = watcher.scan(&index, &writer).unwrap();
+        assert_eq!(1, summary.updated);
+        assert_eq!(1, summary.deleted);
+        assert_eq!(1, reader.searcher().num_docs());
+        assert_eq!(1, repository::query_title(&index, &reader, "发烧", 10).unwrap().len());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
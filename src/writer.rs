@@ -0,0 +1,233 @@
+//! The long-lived index writer shared by the write operations
+//!
+//! tantivy allows a single writer per index, opening one for each request is slow and fails
+//! with a lock error when two requests write at the same time.
+//! The writer is opened once with the index, the documents are added and deleted concurrently,
+//! and committed by the commit policy: after every N changes, every T seconds, or only on demand.
+//!
+
+use std::future::Future;
+use std::sync::{Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use tantivy::{Index, IndexReader, IndexWriter};
+use tracing::{debug, info};
+
+/// Struct containing configurations of the index writer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriterConf {
+    /// Memory budget of the writer in bytes, shared by the indexing threads, at least 15MB per thread
+    #[serde(default = "WriterConf::default_memory_budget")]
+    pub memory_budget: usize,
+    /// Number of the indexing threads, 0 to let tantivy choose by the CPUs
+    #[serde(default)]
+    pub threads: usize,
+    /// Commit after this number of changed documents, 0 to disable
+    #[serde(default = "WriterConf::default_commit_every_docs")]
+    pub commit_every_docs: usize,
+    /// Commit the pending changes every this seconds, 0 to disable
+    #[serde(default)]
+    pub commit_every_secs: u64,
+}
+
+impl WriterConf {
+    fn default_memory_budget() -> usize {
+        50_000_000
+    }
+    /// Commit every write by default, so the changes are searchable when the request returns
+    fn default_commit_every_docs() -> usize {
+        1
+    }
+}
+
+impl Default for WriterConf {
+    fn default() -> Self {
+        Self {
+            memory_budget: Self::default_memory_budget(),
+            threads: 0,
+            commit_every_docs: Self::default_commit_every_docs(),
+            commit_every_secs: 0,
+        }
+    }
+}
+
+/// The result of a commit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitInfo {
+    pub opstamp: u64,
+    /// Number of the changed documents in the commit
+    pub docs: usize,
+    /// Local time of the commit
+    pub committed_at: String,
+}
+
+struct CommitState {
+    /// changed documents since the last commit
+    pending: usize,
+    last_commit: Instant,
+    last_info: Option<CommitInfo>,
+}
+
+pub struct KnowledgeWriter {
+    /// the read lock is taken to add or delete documents, the write lock to commit
+    writer: RwLock<IndexWriter>,
+    reader: IndexReader,
+    conf: WriterConf,
+    state: Mutex<CommitState>,
+}
+
+impl KnowledgeWriter {
+    /// Open the writer of the index
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The reference to the tantivy index
+    /// * `reader` - The reader reloaded after each commit
+    /// * `conf` - The memory budget, threads and commit policy
+    ///
+    /// # Returns
+    ///
+    /// The writer, or error if the index is locked by another writer
+    pub fn open(index: &Index, reader: IndexReader, conf: WriterConf) -> tantivy::Result<Self> {
+        debug!(?conf, "open writer");
+        let writer = if conf.threads > 0 {
+            index.writer_with_num_threads(conf.threads, conf.memory_budget)?
+        } else {
+            index.writer(conf.memory_budget)?
+        };
+        Ok(Self {
+            writer: RwLock::new(writer),
+            reader,
+            conf,
+            state: Mutex::new(CommitState {
+                pending: 0,
+                last_commit: Instant::now(),
+                last_info: None,
+            }),
+        })
+    }
+
+    /// The reader of the index, reloaded after each commit
+    pub fn reader(&self) -> &IndexReader {
+        &self.reader
+    }
+
+    pub fn conf(&self) -> &WriterConf {
+        &self.conf
+    }
+
+    /// The writer to add or delete documents
+    ///
+    /// The guard must be dropped before `changed` or `commit` is called.
+    pub fn writer(&self) -> RwLockReadGuard<'_, IndexWriter> {
+        self.writer.read().unwrap()
+    }
+
+    /// Record the documents added or deleted, and commit if `commit_every_docs` is reached
+    ///
+    /// # Returns
+    ///
+    /// The commit, if there is one
+    pub fn changed(&self, docs: usize) -> tantivy::Result<Option<CommitInfo>> {
+        let pending = {
+            let mut state = self.state.lock().unwrap();
+            state.pending += docs;
+            state.pending
+        };
+        if self.conf.commit_every_docs > 0 && pending >= self.conf.commit_every_docs {
+            return self.commit().map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Commit the pending changes and reload the reader
+    pub fn commit(&self) -> tantivy::Result<CommitInfo> {
+        let mut writer = self.writer.write().unwrap();
+        let opstamp = writer.commit()?;
+        self.reader.reload()?;
+        let mut state = self.state.lock().unwrap();
+        let info = CommitInfo {
+            opstamp,
+            docs: state.pending,
+            committed_at: Local::now().format("%Y-%m-%dT%H:%M:%S%.3f").to_string(),
+        };
+        state.pending = 0;
+        state.last_commit = Instant::now();
+        state.last_info = Some(info.clone());
+        Ok(info)
+    }
+
+    /// Commit the pending changes if `commit_every_secs` has passed since the last commit
+    pub fn commit_if_due(&self) -> tantivy::Result<Option<CommitInfo>> {
+        let due = {
+            let state = self.state.lock().unwrap();
+            self.conf.commit_every_secs > 0
+                && state.pending > 0
+                && state.last_commit.elapsed() >= Duration::from_secs(self.conf.commit_every_secs)
+        };
+        if due {
+            return self.commit().map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Number of the changed documents not committed yet
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().pending
+    }
+
+    /// The last commit by this writer
+    pub fn last_commit(&self) -> Option<CommitInfo> {
+        self.state.lock().unwrap().last_info.clone()
+    }
+}
+
+/// Run `commit_due` every `secs` until the process exits, e.g. `AppState::commit_due`
+pub async fn run_commit_timer<F, Fut>(secs: u64, mut commit_due: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    info!(secs, "commit timer");
+    let mut interval = tokio::time::interval(Duration::from_secs(secs.max(1)));
+    loop {
+        interval.tick().await;
+        commit_due().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::tests::test_repository_with;
+    use crate::repository::{self, KnownledgeDocument, TextTokenizer};
+
+    #[test]
+    fn test_commit_policy() {
+        let conf = WriterConf {
+            commit_every_docs: 3,
+            ..Default::default()
+        };
+        let (_test, index, reader, writer) =
+            test_repository_with("index_test_writer", TextTokenizer::default(), conf);
+        let doc = |title: &str| KnownledgeDocument::new(title.to_string(), "".to_string(), None, vec![]);
+
+        repository::add_doc_in_batch(&index, &writer, vec![doc("a"), doc("b")]).unwrap();
+        assert_eq!(2, writer.pending());
+        assert_eq!(0, reader.searcher().num_docs());
+        repository::add_doc(&index, &writer, doc("c")).unwrap();
+        assert_eq!(0, writer.pending());
+        assert_eq!(3, reader.searcher().num_docs());
+
+        repository::add_doc(&index, &writer, doc("d")).unwrap();
+        let info = writer.commit().unwrap();
+        assert_eq!(1, info.docs);
+        assert_eq!(4, reader.searcher().num_docs());
+        assert_eq!(Some(info.opstamp), writer.last_commit().map(|i| i.opstamp));
+
+        //a second writer can't be opened on the same index
+        assert!(KnowledgeWriter::open(&index, reader, WriterConf::default()).is_err());
+    }
+}